@@ -0,0 +1,152 @@
+use crate::{EEPROMResult, Flash, HalfWord, Layout, Params, EEPROM};
+
+/// Write-back cache on top of the `EEPROM` controller. Buffers up to `N` dirty values in RAM,
+/// coalescing repeated writes to the same tag, and writes them to the flash on `flush`.
+///
+/// Values are flushed automatically when the buffer is full or when the configured amount of
+/// writes is reached (see `set_flush_threshold`). Since buffered values are lost on reset,
+/// application should also flush them when it is about to lose power, for example, from the
+/// power voltage detector (PVD) interrupt handler. Use `try_flush` there: `flush` compacts the
+/// active page when it is full, erasing a page, which should never happen while the supply
+/// voltage is dropping. Application should keep space for the dirty values on the active page
+/// by compacting it (see `EEPROM::needs_compaction`) while power is good.
+///
+/// # Examples
+/// ```rust,no_run
+/// # use eeprom::{CachedEEPROM, EEPROM, Flash, Params};
+/// # fn example<F: Flash>(eeprom: EEPROM<F>) {
+/// let mut cache: CachedEEPROM<_, _, 8> = CachedEEPROM::new(eeprom);
+/// cache.set_flush_threshold(100);
+/// for odometer in 0..50 {
+///     // Only kept in RAM
+///     cache.write(1, odometer).unwrap();
+/// }
+/// // In PVD interrupt handler
+/// cache.try_flush().unwrap();
+/// # }
+/// ```
+pub struct CachedEEPROM<F, L = Params, const N: usize = 16> {
+    eeprom: EEPROM<F, L>,
+    // Dirty values, first `len` entries are used
+    entries: [(HalfWord, HalfWord); N],
+    len: usize,
+    // Amount of writes since the last flush
+    writes: u32,
+    flush_threshold: Option<u32>,
+}
+
+impl<F, L, const N: usize> CachedEEPROM<F, L, N>
+where
+    F: Flash,
+    L: Layout,
+{
+    /// Create write-back cache on top of the EEPROM controller. Controller should be initialized.
+    pub fn new(eeprom: EEPROM<F, L>) -> Self {
+        CachedEEPROM {
+            eeprom,
+            entries: [(0, 0); N],
+            len: 0,
+            writes: 0,
+            flush_threshold: None,
+        }
+    }
+
+    /// Flush dirty values automatically after the given amount of writes (`None` disables
+    /// flushing by count).
+    pub fn set_flush_threshold(&mut self, writes: impl Into<Option<u32>>) {
+        self.flush_threshold = writes.into();
+    }
+
+    /// Read value for a specified tag, either from the cache or from the EEPROM.
+    ///
+    /// # Panics
+    /// * see `EEPROM::read`
+    pub fn read(&mut self, tag: HalfWord) -> Option<HalfWord> {
+        match self.entries[..self.len].iter().find(|entry| entry.0 == tag) {
+            Some(&(_, data)) => Some(data),
+            None => self.eeprom.read(tag),
+        }
+    }
+
+    /// Write value for a specified tag to the cache. Flushes the cache if it is full or the
+    /// flush threshold is reached.
+    ///
+    /// # Panics
+    /// * panics if tag value has the most significant bit set to `1` (reserved value)
+    /// * see `EEPROM::write`
+    pub fn write(&mut self, tag: HalfWord, data: HalfWord) -> EEPROMResult<()> {
+        assert_eq!(tag & 0b1000_0000_0000_0000, 0, "msb bit of `1` is reserved");
+
+        if let Some(entry) = self.entries[..self.len].iter_mut().find(|e| e.0 == tag) {
+            entry.1 = data;
+        } else {
+            if self.len == N {
+                self.flush()?;
+            }
+            self.entries[self.len] = (tag, data);
+            self.len += 1;
+        }
+
+        self.writes += 1;
+        if self
+            .flush_threshold
+            .is_some_and(|threshold| self.writes >= threshold)
+        {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write all dirty values to the EEPROM. Values equal to the stored ones are not written.
+    /// If writing fails, values which were not written yet are kept in the cache.
+    pub fn flush(&mut self) -> EEPROMResult<()> {
+        self.flush_with(|eeprom, tag, data| eeprom.update(tag, data).map(drop))
+    }
+
+    /// Write all dirty values to the EEPROM, unless the active page is full. Never compacts or
+    /// erases pages (see `EEPROM::try_write`), so it is safe to call on a power loss. Returns
+    /// `Error::WouldCompact` if the page fills up, keeping values which were not written yet in
+    /// the cache.
+    pub fn try_flush(&mut self) -> EEPROMResult<()> {
+        self.flush_with(|eeprom, tag, data| match eeprom.read(tag) {
+            Some(current) if current == data => Ok(()),
+            _ => eeprom.try_write(tag, data),
+        })
+    }
+
+    fn flush_with<W>(&mut self, mut store: W) -> EEPROMResult<()>
+    where
+        W: FnMut(&mut EEPROM<F, L>, HalfWord, HalfWord) -> EEPROMResult<()>,
+    {
+        let mut flushed = 0;
+        let result = self.entries[..self.len]
+            .iter()
+            .try_for_each(|&(tag, data)| {
+                store(&mut self.eeprom, tag, data)?;
+                flushed += 1;
+                Ok(())
+            });
+        self.entries.copy_within(flushed..self.len, 0);
+        self.len -= flushed;
+        if result.is_ok() {
+            self.writes = 0;
+        }
+        result
+    }
+
+    /// Check if there are values not written to the EEPROM yet.
+    pub fn is_dirty(&self) -> bool {
+        self.len != 0
+    }
+
+    /// Access the underlying EEPROM controller. Note that it does not see values which are not
+    /// flushed yet.
+    pub fn eeprom(&mut self) -> &mut EEPROM<F, L> {
+        &mut self.eeprom
+    }
+
+    /// Return the underlying EEPROM controller, dropping values which are not flushed.
+    pub fn into_inner(self) -> EEPROM<F, L> {
+        self.eeprom
+    }
+}
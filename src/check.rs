@@ -0,0 +1,88 @@
+use crate::Error;
+
+/// State of a page, as seen by `EEPROM::check_page`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PageState {
+    /// Page is blank
+    Erased,
+    /// Page is marked as active
+    Active,
+    /// Page was compacted to the next page, but not erased yet
+    Obsolete,
+    /// Page is being filled by the compaction and is not marked as active yet
+    Incomplete,
+    /// Page is active and written by eeprom 0.3 (migrated by `init`)
+    Legacy,
+    /// Page was retired because it kept failing to erase or program
+    Retired,
+    /// Page holds data in an unknown format (erased by `init` unless it is the only active page)
+    Unknown,
+}
+
+/// Consistency report of a single page (see `EEPROM::check_page`).
+///
+/// Counter record is live while the latest value of its counter belongs to it. Every item after the
+/// page header falls into exactly one of the live, stale, erased and corrupt categories. Items are
+/// not counted for retired and unknown pages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PageReport {
+    /// State of the page
+    pub state: PageState,
+    /// Generation of the page, if page header is written in the current format
+    pub generation: Option<u32>,
+    /// Amount of items holding the latest values (only on the active page)
+    pub live_items: u32,
+    /// Amount of items which are overwritten, superseded by the compaction, or discarded by
+    /// `init` (values of an incomplete transaction)
+    pub stale_items: u32,
+    /// Amount of erased items
+    pub erased_items: u32,
+    /// Amount of items which are marked invalid or could not be parsed
+    pub corrupt_items: u32,
+    /// Amount of items written after the first erased item (never happens, as items are always
+    /// appended)
+    pub trailing_items: u32,
+}
+
+/// Consistency report of all pages (see `EEPROM::check`). Item counts are summed over all pages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Report {
+    /// Active page used by `read` and `write`
+    pub active: Option<u32>,
+    /// Error `init` would return without modifying the flash
+    pub error: Option<Error>,
+    /// Amount of pages marked as active (more than one if compaction was interrupted)
+    pub active_pages: u32,
+    /// Amount of retired pages
+    pub retired_pages: u32,
+    /// Amount of pages in an unknown format
+    pub unknown_pages: u32,
+    /// Estimated amount of erases of every usable page: every compaction erases one page, so
+    /// compactions so far (generation of the active page) are spread evenly over the pages which
+    /// are not retired, rounding up. Erase counts are not stored on the flash, so this does not
+    /// include erases by `erase` (which resets the generation), `migrate_layout` or retries.
+    pub estimated_erases: Option<u32>,
+    /// Amount of items holding the latest values
+    pub live_items: u32,
+    /// Amount of stale items
+    pub stale_items: u32,
+    /// Amount of erased items
+    pub erased_items: u32,
+    /// Amount of corrupt items
+    pub corrupt_items: u32,
+    /// Amount of items written after the first erased item of the page
+    pub trailing_items: u32,
+}
+
+impl Report {
+    /// Check that EEPROM is consistent: exactly one active page in the current format, no pages
+    /// in an unknown format, and no corrupt or trailing items.
+    pub fn is_consistent(&self) -> bool {
+        self.error.is_none()
+            && self.active.is_some()
+            && self.active_pages == 1
+            && self.unknown_pages == 0
+            && self.corrupt_items == 0
+            && self.trailing_items == 0
+    }
+}
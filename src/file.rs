@@ -0,0 +1,114 @@
+use crate::{EEPROMExt, Flash, FlashResult, HalfWord, Params, EEPROM};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::vec;
+
+const ERASED_BYTE: u8 = 0xff;
+
+/// `Flash` implementation backed by a regular file, for host builds and integration tests.
+///
+/// File holds only the flash region described by the `Params` passed to `open` (`page_count`
+/// pages starting at `first_page`), so offsets passed by the EEPROM controller are translated
+/// relative to the beginning of that region. Region could span several EEPROM regions (for
+/// example, both regions of `MirroredEEPROM`), each of them is stored at its own place in the
+/// file. Writes follow NOR flash semantics: programming can only clear bits, setting them back
+/// to `1` requires erasing the whole page.
+pub struct FileFlash {
+    file: File,
+    // Flash offsets of the beginning and the end of the region stored in the file
+    start: u32,
+    end: u32,
+}
+
+impl FileFlash {
+    /// Open file at `path` as a flash storage for the region described by `params`.
+    /// File is created if it does not exist; missing part of the region is filled with the
+    /// erased (`0xff`) bytes.
+    pub fn open<P: AsRef<Path>>(path: P, params: &Params) -> io::Result<FileFlash> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let size = u64::from(params.page_count * params.page_size);
+        let len = file.metadata()?.len();
+        if len < size {
+            file.seek(SeekFrom::Start(len))?;
+            file.write_all(&vec![ERASED_BYTE; (size - len) as usize])?;
+            file.flush()?;
+        }
+        let start = params.first_page * params.page_size;
+        Ok(FileFlash {
+            file,
+            start,
+            end: start + params.page_count * params.page_size,
+        })
+    }
+
+    fn seek(&mut self, offset: u32, len: u32) -> io::Result<()> {
+        if offset < self.start || offset + len > self.end {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "offset is outside of the flash region",
+            ));
+        }
+        self.file
+            .seek(SeekFrom::Start(u64::from(offset - self.start)))?;
+        Ok(())
+    }
+
+    fn read_half_word(&mut self, offset: u32) -> io::Result<HalfWord> {
+        self.seek(offset, 2)?;
+        let mut buf = [0u8; 2];
+        self.file.read_exact(&mut buf)?;
+        Ok(HalfWord::from_le_bytes(buf))
+    }
+
+    fn write_half_word(&mut self, offset: u32, data: HalfWord) -> io::Result<()> {
+        // NOR flash can only clear bits when programming
+        let data = self.read_half_word(offset)? & data;
+        self.seek(offset, 2)?;
+        self.file.write_all(&data.to_le_bytes())?;
+        self.file.flush()
+    }
+
+    fn erase(&mut self, params: &Params, address: u32) -> io::Result<()> {
+        if !address.is_multiple_of(params.page_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "address is not aligned to the page boundary",
+            ));
+        }
+        self.seek(address, params.page_size)?;
+        self.file
+            .write_all(&vec![ERASED_BYTE; params.page_size as usize])?;
+        self.file.flush()
+    }
+}
+
+impl EEPROMExt for &mut FileFlash {
+    fn eeprom(self, params: Params) -> EEPROM<Self> {
+        EEPROM::new(params, self)
+    }
+}
+
+impl Flash for &mut FileFlash {
+    fn read(&mut self, _params: &Params, offset: u32) -> FlashResult<HalfWord> {
+        self.read_half_word(offset).map_err(|_| ())
+    }
+
+    fn write(&mut self, _params: &Params, offset: u32, data: HalfWord) -> FlashResult<()> {
+        self.write_half_word(offset, data).map_err(|_| ())
+    }
+
+    fn page_erase(&mut self, params: &Params, address: u32) -> FlashResult<()> {
+        self.erase(params, address).map_err(|_| ())
+    }
+
+    fn clears_bits(&self) -> bool {
+        true
+    }
+}
@@ -38,49 +38,283 @@
 //! # }
 //! ```
 //!
+//! # Linker script
+//! EEPROM region could be reserved in the linker script (`memory.x`) and `Params` derived from it
+//! via `Params::from_linker`, so the region is defined in a single place:
+//!
+//! ```text
+//! MEMORY
+//! {
+//!   FLASH : ORIGIN = 0x08000000, LENGTH = 60K
+//!   EEPROM : ORIGIN = ORIGIN(FLASH) + LENGTH(FLASH), LENGTH = 4K
+//!   RAM : ORIGIN = 0x20000000, LENGTH = 20K
+//! }
+//!
+//! _eeprom_start = ORIGIN(EEPROM);
+//! _page_size = 1K;
+//! _eeprom_pages = LENGTH(EEPROM) / _page_size;
+//! ```
+//!
+//! # On-flash format
+//! Every page starts with a header, which holds page status, format version, magic and a
+//! fingerprint of the `Params` used to write the page, followed by a generation counter incremented
+//! every time data is compacted to the next page. `init` refuses to touch an active page written in an
+//! unknown or incompatible format (returning an error instead of erasing it); `erase` can be
+//! used to format EEPROM from scratch.
+//!
+//! Pages written by eeprom 0.3 (`0xABCD` status followed by `0xFFFF` instead of the format version)
+//! are recognized by `init`, which migrates live values to the next page in the current format.
+//!
+//! Counters (see `EEPROM::increment`) are stored as items holding the low bits of the counter,
+//! like other values. Item written when the counter is created or carries over to the high bits is
+//! followed by a record holding the high bits.
+//!
+//! Values written by `EEPROM::transaction` are preceded by a record holding the amount of values in
+//! the transaction. If writing them is interrupted, `init` compacts the page, discarding the
+//! values of the incomplete transaction.
+//!
+//! With more than two pages configured, a page which keeps failing to erase or program in the
+//! verify mode (see `EEPROM::set_verify`) is retired: it is marked with zero status and version and
+//! is skipped by compaction afterwards. At least two pages are always kept in use.
+//!
 //! # Panics
 //! EEPROM controller will panic in the following cases:
 //!
 //! * No free space on the page even after compaction
 //! * active page cannot be found during `read`/`write` operation (`init` makes sure that there
 //!   is exactly one active page.
+//!
+//! # Bootloaders
+//! `EEPROMReader` reads values without ever modifying the flash, so it could be used by code which
+//! must not erase or compact pages. It only needs `ReadFlash`, which could be implemented on top of
+//! the memory-mapped flash (`MappedFlash`).
+//!
+//! # Host builds
+//! With the `std` feature enabled, `FileFlash` provides a `Flash` implementation backed by
+//! a regular file, so the same EEPROM logic can run (and persist its data) on a host machine.
+//! On embedded Linux, the `mtd` feature adds `MtdFlash` which works on top of the raw NOR
+//! partitions exposed as `/dev/mtdN`.
 #![no_std]
 #![warn(missing_docs)]
+// Without HAL, flash operations report `()` as an error
+#![allow(clippy::result_unit_err)]
 //#![deny(warnings)]
 
-#[cfg(test)]
-#[macro_use]
+#[cfg(any(test, feature = "std"))]
+#[cfg_attr(test, macro_use)]
 extern crate std;
 
 #[cfg(test)]
 mod tests;
 
+mod cache;
+mod check;
+mod mirror;
+mod pages;
+mod reader;
+mod transaction;
+
+#[cfg(all(feature = "std", not(feature = "stm32f103")))]
+mod file;
+
+#[cfg(all(feature = "mtd", target_os = "linux", not(feature = "stm32f103")))]
+mod mtd;
+
+pub use crate::cache::CachedEEPROM;
+pub use crate::check::{PageReport, PageState, Report};
+#[cfg(all(feature = "std", not(feature = "stm32f103")))]
+pub use crate::file::FileFlash;
+pub use crate::mirror::MirroredEEPROM;
+#[cfg(all(feature = "mtd", target_os = "linux", not(feature = "stm32f103")))]
+pub use crate::mtd::{MtdFlash, MtdInfo};
+pub use crate::reader::{EEPROMReader, MappedFlash};
+pub use crate::transaction::{Transaction, MAX_TRANSACTION_ITEMS};
+
+use crate::pages::Pages;
+
 use core::mem::size_of;
+use core::ops::Range;
 use core::option::Option;
 use core::result::Result;
 #[cfg(feature = "stm32f103")]
-use stm32f1xx_hal::flash::{Error as FlashError, FlashSize, Parts, SectorSize};
+use stm32f1xx_hal::flash::{Error as FlashError, FlashSize, FlashWriter, Parts, SectorSize};
 
 #[cfg(not(feature = "stm32f103"))]
 type FlashError = ();
 #[cfg(not(feature = "stm32f103"))]
-type SectorSize = u32;
-#[cfg(not(feature = "stm32f103"))]
 type FlashSize = u32;
 
 /// Result type for flash operations.
 type FlashResult<T> = Result<T, FlashError>;
 
+/// Result type for EEPROM operations.
+type EEPROMResult<T> = Result<T, Error>;
+
 // STM32 allows programming half-words
 type HalfWord = u16;
 type Word = u32;
 
 const ACTIVE_PAGE_MARKER: HalfWord = 0xABCD;
+// Page, which was compacted to the next page, but not erased yet. STM32 only allows programming
+// zero over a non-erased half-word.
+const OBSOLETE_PAGE_MARKER: HalfWord = 0x0000;
 const ERASED_ITEM: Word = 0xffff_ffff; // two u16 half-words
 
 // Each item is 16-bit tag plus 16-bit value
 const ITEM_SIZE: u32 = size_of::<Word>() as u32;
 
+// Amount of items read from the flash at once when scanning pages
+const SCAN_ITEMS: u32 = 16;
+
+// STM32 maps flash memory at this address
+const FLASH_START: u32 = 0x0800_0000;
+
+// Symbols defined in the linker script. Only their addresses are meaningful.
+extern "C" {
+    static _eeprom_start: u8;
+    static _page_size: u8;
+    static _eeprom_pages: u8;
+}
+
+// Page header layout, in half-words. Page status is written last, so the rest of the header is
+// valid once the page is marked as active.
+const HEADER_STATUS: u32 = 0;
+const HEADER_VERSION: u32 = 1;
+const HEADER_MAGIC: u32 = 2;
+const HEADER_FINGERPRINT: u32 = 3;
+const HEADER_GENERATION: u32 = 4; // two half-words, low half-word first
+
+// Amount of items occupied by the page header
+const HEADER_ITEMS: u32 = 3;
+
+// Amount of items kept free on the compacted page, so a value or a counter (which takes two items,
+// see `EEPROM::increment`) could always be written after the compaction
+const RESERVED_ITEMS: u32 = 2;
+
+// Maximum amount of slots taken by the live values of the page
+const fn page_capacity(page_items: u32) -> u32 {
+    page_items.saturating_sub(HEADER_ITEMS + RESERVED_ITEMS)
+}
+
+const FORMAT_MAGIC: HalfWord = 0x4545; // "EE"
+const FORMAT_VERSION: HalfWord = 1;
+// Version of the page which is retired because it kept failing to erase or program
+const BAD_PAGE_VERSION: HalfWord = 0;
+
+// Record preceding the values written by a transaction, data is the amount of values. Transaction
+// is committed once all of its values are written.
+const TRANSACTION_TAG: HalfWord = 0xfffe;
+
+// Record following the value of a counter, data is the upper half of the counter value (the value
+// item holds the lower half). Values of the same tag written after it belong to the counter as well.
+const COUNTER_TAG: HalfWord = 0x9000;
+
+// Tags with the most significant bit set are reserved for erased items, transaction and counter
+// records
+fn is_value_tag(tag: HalfWord) -> bool {
+    tag & 0b1000_0000_0000_0000 == 0
+}
+
+// Item which was read back with different data in the verify mode, and is skipped
+const INVALID_TAG: HalfWord = 0x8000;
+
+// Amount of retries of the page erase in the verify mode
+const ERASE_RETRIES: u32 = 3;
+
+// Amount of retries of writing an item to the next free slot in the verify mode
+const PROGRAM_RETRIES: u32 = 3;
+
+fn is_counter_record(tag: HalfWord) -> bool {
+    tag == COUNTER_TAG
+}
+
+// Amount of items taken by the copy of the value, given the upper half of the counter it belongs to
+fn copy_slots(high: Option<HalfWord>) -> u32 {
+    if high.is_some() {
+        2
+    } else {
+        1
+    }
+}
+
+/// EEPROM errors
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Flash operation failed
+    Flash(FlashError),
+    /// Active page is not written in the format of this crate
+    UnknownFormat,
+    /// Active page is written in an unsupported version of the format
+    UnsupportedVersion(u16),
+    /// Active page was written with different `Params`
+    ParamsMismatch,
+    /// Active page is full and writing would require compaction (see `try_write`)
+    WouldCompact,
+    /// New tag would not fit into the page after compaction (see `capacity`), or values of the
+    /// page written by eeprom 0.3 do not fit into the page in the current format
+    CapacityExceeded,
+    /// Both copies of the value are corrupted (see `MirroredEEPROM`), or the tag of the item did
+    /// not verify and the item could not be marked invalid, so it might hold a wrong value of
+    /// another tag (see `set_verify`)
+    Corrupted,
+    /// Flash contents do not match the written data even after retries (see `set_verify`)
+    Verify,
+    /// Parameters are not valid (see `Params::validate`) or cannot be migrated to (see
+    /// `EEPROM::migrate_layout`)
+    InvalidParams(ParamsError),
+}
+
+/// `Params` validation errors
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParamsError {
+    /// Page size is zero or is not a multiple of the item size (4 bytes)
+    InvalidPageSize,
+    /// Page is too small to fit the page header, the items reserved for compaction and at least
+    /// one value
+    PageTooSmall,
+    /// Page size is not supported by the flash (with `stm32f103`, only 1K, 2K and 4K sectors
+    /// are supported by the HAL)
+    UnsupportedPageSize,
+    /// Less than two pages are configured (at least two are required for compaction), or the
+    /// region has no usable page to migrate values to (see `EEPROM::migrate_layout`)
+    TooFewPages,
+    /// EEPROM region does not fit into the flash
+    OutOfBounds,
+    /// EEPROM region does not start at the page boundary
+    UnalignedStart,
+    /// EEPROM region overlaps the program image (see `Params::validate_program_end`)
+    OverlapsProgram,
+    /// Page size differs from the page size of the region values are migrated from (see
+    /// `EEPROM::migrate_layout`)
+    PageSizeMismatch,
+}
+
+impl From<FlashError> for Error {
+    fn from(err: FlashError) -> Self {
+        Error::Flash(err)
+    }
+}
+
+impl From<ParamsError> for Error {
+    fn from(err: ParamsError) -> Self {
+        Error::InvalidParams(err)
+    }
+}
+
+/// Counters of the failures detected in the verify mode (see `EEPROM::set_verify`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Diagnostics {
+    /// Amount of half-words which were read back with different data after programming
+    pub program_failures: u32,
+    /// Amount of items marked invalid and skipped because their data was not programmed
+    /// correctly
+    pub invalid_items: u32,
+    /// Amount of page erases which were retried because page was not blank afterwards
+    pub erase_retries: u32,
+    /// Amount of pages retired because they kept failing to erase or program
+    pub retired_pages: u32,
+}
+
 /// EEPROM configuration parameters
 #[derive(Clone, Copy, Debug)]
 pub struct Params {
@@ -88,12 +322,291 @@ pub struct Params {
     pub first_page: u32,
     /// Total size of the flash
     pub flash_size: FlashSize,
-    /// Size of the EEPROM page
-    pub page_size: SectorSize,
+    /// Size of the EEPROM page, in bytes
+    pub page_size: u32,
     /// Count of EEPROM pages
     pub page_count: u32,
 }
 
+impl Params {
+    /// Create parameters from the `_eeprom_start`, `_page_size` and `_eeprom_pages` linker
+    /// symbols (see crate documentation for the `memory.x` fragment defining them). Symbols are
+    /// only referenced (and required at link time) if this function is used.
+    ///
+    /// `_eeprom_start` is an address of the first EEPROM page in the flash memory, `_page_size`
+    /// is a size of the page in bytes and `_eeprom_pages` is an amount of pages. Returns an error
+    /// if the region does not start at the page boundary or is not valid (see `validate`).
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use eeprom::Params;
+    /// # // Fake linker variables
+    /// # #[export_name = "_eeprom_start"] pub static EEPROM_START: u32 = 0;
+    /// # #[export_name = "_page_size"] pub static PAGE_SIZE: u32 = 0;
+    /// # #[export_name = "_eeprom_pages"] pub static EEPROM_PAGES: u32 = 0;
+    /// let params = Params::from_linker(64 * 1024).expect("invalid EEPROM region in memory.x");
+    /// ```
+    pub fn from_linker(flash_size: FlashSize) -> Result<Params, ParamsError> {
+        let (start, page_size, pages) = unsafe {
+            (
+                &_eeprom_start as *const u8 as usize as u32,
+                &_page_size as *const u8 as usize as u32,
+                &_eeprom_pages as *const u8 as usize as u32,
+            )
+        };
+        Params::from_region(start, page_size, pages, flash_size)
+    }
+
+    // Create parameters for the region starting at `start` address
+    fn from_region(
+        start: u32,
+        page_size: u32,
+        pages: u32,
+        flash_size: FlashSize,
+    ) -> Result<Params, ParamsError> {
+        if page_size == 0 {
+            return Err(ParamsError::InvalidPageSize);
+        }
+        let offset = match start.checked_sub(FLASH_START) {
+            Some(offset) => offset,
+            None => return Err(ParamsError::OutOfBounds),
+        };
+        // Rounding the start down would put EEPROM into the program flash
+        if !offset.is_multiple_of(page_size) {
+            return Err(ParamsError::UnalignedStart);
+        }
+
+        let params = Params {
+            first_page: offset / page_size,
+            flash_size,
+            page_size,
+            page_count: pages,
+        };
+        params.validate()?;
+        Ok(params)
+    }
+
+    /// Validate EEPROM geometry: page size, amount of pages and that EEPROM region fits into
+    /// the flash.
+    pub const fn validate(&self) -> Result<(), ParamsError> {
+        let page_size = self.page_size;
+        if page_size == 0 || !page_size.is_multiple_of(ITEM_SIZE) {
+            return Err(ParamsError::InvalidPageSize);
+        }
+        if page_capacity(page_size / ITEM_SIZE) < 1 {
+            return Err(ParamsError::PageTooSmall);
+        }
+        if !is_sector_size(page_size) {
+            return Err(ParamsError::UnsupportedPageSize);
+        }
+        if self.page_count < 2 {
+            return Err(ParamsError::TooFewPages);
+        }
+
+        let pages = match self.first_page.checked_add(self.page_count) {
+            Some(pages) => pages,
+            None => return Err(ParamsError::OutOfBounds),
+        };
+        match pages.checked_mul(page_size) {
+            Some(end) if end <= self.flash_bytes() => Ok(()),
+            _ => Err(ParamsError::OutOfBounds),
+        }
+    }
+
+    /// Validate EEPROM geometry (see `validate`) and check that EEPROM region does not overlap
+    /// the program image, which occupies the flash up to `program_end` (offset of the end of the
+    /// image from the beginning of the flash, in bytes). EEPROM region must be placed after the
+    /// program image.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use eeprom::{Params, ParamsError};
+    /// let params = Params {
+    ///     first_page: 60,
+    ///     flash_size: 64 * 1024,
+    ///     page_size: 1024,
+    ///     page_count: 4,
+    /// };
+    /// assert_eq!(Ok(()), params.validate_program_end(60 * 1024));
+    /// assert_eq!(
+    ///     Err(ParamsError::OverlapsProgram),
+    ///     params.validate_program_end(60 * 1024 + 1)
+    /// );
+    /// ```
+    pub const fn validate_program_end(&self, program_end: u32) -> Result<(), ParamsError> {
+        if let Err(err) = self.validate() {
+            return Err(err);
+        }
+        // Cannot overflow, as region was checked to fit into the flash
+        if self.first_page * self.page_size < program_end {
+            Err(ParamsError::OverlapsProgram)
+        } else {
+            Ok(())
+        }
+    }
+
+    // `flash_size` is a HAL enum (in kilobytes) when `stm32f103` feature is enabled
+    #[cfg(feature = "stm32f103")]
+    const fn flash_bytes(&self) -> u32 {
+        (self.flash_size as u32) * 1024
+    }
+
+    #[cfg(not(feature = "stm32f103"))]
+    const fn flash_bytes(&self) -> u32 {
+        self.flash_size
+    }
+
+    // Fingerprint of the EEPROM layout (32-bit FNV-1a folded to 16 bits), stored in the page
+    // header to detect pages written with different parameters
+    fn fingerprint(&self) -> HalfWord {
+        let mut hash: u32 = 0x811c_9dc5;
+        for value in [self.first_page, self.page_size, self.page_count] {
+            for byte in value.to_le_bytes() {
+                hash ^= u32::from(byte);
+                hash = hash.wrapping_mul(0x0100_0193);
+            }
+        }
+        ((hash >> 16) ^ hash) as HalfWord
+    }
+}
+
+/// Size of the HAL sector, in bytes, to be used as `Params::page_size`.
+#[cfg(feature = "stm32f103")]
+pub const fn sector_bytes(sector: SectorSize) -> u32 {
+    sector as u32 * 1024
+}
+
+// Check if the page size matches one of the HAL sector sizes (see `sector_size`)
+#[cfg(feature = "stm32f103")]
+const fn is_sector_size(page_size: u32) -> bool {
+    page_size == sector_bytes(SectorSize::Sz1K)
+        || page_size == sector_bytes(SectorSize::Sz2K)
+        || page_size == sector_bytes(SectorSize::Sz4K)
+}
+
+#[cfg(not(feature = "stm32f103"))]
+const fn is_sector_size(_: u32) -> bool {
+    true
+}
+
+// Inverse of `sector_bytes`, used to create HAL flash writer. Page size is checked by
+// `Params::validate`.
+#[cfg(feature = "stm32f103")]
+fn sector_size(page_size: u32) -> SectorSize {
+    [SectorSize::Sz1K, SectorSize::Sz2K, SectorSize::Sz4K]
+        .into_iter()
+        .find(|&sector| sector_bytes(sector) == page_size)
+        .expect("page size is not supported by the HAL")
+}
+
+#[cfg(feature = "stm32f103")]
+fn writer<'a>(parts: &'a mut Parts, params: &Params) -> FlashWriter<'a> {
+    parts.writer(sector_size(params.page_size), params.flash_size)
+}
+
+/// EEPROM geometry. Implemented by `Params` for geometry defined at runtime and by `ConstLayout`
+/// for geometry defined (and validated) at compile time.
+pub trait Layout {
+    /// EEPROM parameters
+    fn params(&self) -> Params;
+
+    /// Amount of items per page, including the page header
+    fn page_items(&self) -> u32 {
+        self.params().page_size / ITEM_SIZE
+    }
+}
+
+impl Layout for Params {
+    fn params(&self) -> Params {
+        *self
+    }
+}
+
+/// EEPROM geometry defined at compile time: index of the first page, page size in bytes, amount
+/// of pages and total size of the flash in bytes. Geometry is validated at compile time, page
+/// size is a constant for the EEPROM controller code, and `PAGE_ITEMS`/`CAPACITY` constants
+/// could be used to size RAM buffers.
+///
+/// # Examples
+/// ```rust
+/// # use eeprom::ConstLayout;
+/// type Layout = ConstLayout<60, 1024, 4, { 64 * 1024 }>;
+/// let mut tags = [0u16; Layout::CAPACITY];
+/// # assert_eq!(251, tags.len());
+/// ```
+///
+/// Invalid geometry is rejected at compile time:
+/// ```rust,compile_fail
+/// # use eeprom::ConstLayout;
+/// // At least two pages are required
+/// let params = ConstLayout::<60, 1024, 1, { 64 * 1024 }>::PARAMS;
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConstLayout<
+    const FIRST_PAGE: u32,
+    const PAGE_SIZE: u32,
+    const PAGE_COUNT: u32,
+    const FLASH_SIZE: u32,
+>;
+
+impl<const FIRST_PAGE: u32, const PAGE_SIZE: u32, const PAGE_COUNT: u32, const FLASH_SIZE: u32>
+    ConstLayout<FIRST_PAGE, PAGE_SIZE, PAGE_COUNT, FLASH_SIZE>
+{
+    /// EEPROM parameters. Fails to compile if geometry is not valid.
+    pub const PARAMS: Params = {
+        let params = Params {
+            first_page: FIRST_PAGE,
+            flash_size: flash_size(FLASH_SIZE),
+            page_size: PAGE_SIZE,
+            page_count: PAGE_COUNT,
+        };
+        match params.validate() {
+            Ok(()) => params,
+            Err(_) => panic!("invalid EEPROM layout"),
+        }
+    };
+
+    /// Amount of items per page, including the page header
+    pub const PAGE_ITEMS: usize = (Self::PARAMS.page_size / ITEM_SIZE) as usize;
+
+    /// Maximum amount of distinct tags that could be stored (see `EEPROM::capacity`)
+    pub const CAPACITY: usize = page_capacity(Self::PARAMS.page_size / ITEM_SIZE) as usize;
+}
+
+impl<const FIRST_PAGE: u32, const PAGE_SIZE: u32, const PAGE_COUNT: u32, const FLASH_SIZE: u32>
+    Layout for ConstLayout<FIRST_PAGE, PAGE_SIZE, PAGE_COUNT, FLASH_SIZE>
+{
+    fn params(&self) -> Params {
+        Self::PARAMS
+    }
+
+    fn page_items(&self) -> u32 {
+        Self::PAGE_ITEMS as u32
+    }
+}
+
+// Convert flash size in bytes to `FlashSize`
+#[cfg(feature = "stm32f103")]
+const fn flash_size(bytes: u32) -> FlashSize {
+    match bytes / 1024 {
+        16 => FlashSize::Sz16K,
+        32 => FlashSize::Sz32K,
+        64 => FlashSize::Sz64K,
+        128 => FlashSize::Sz128K,
+        256 => FlashSize::Sz256K,
+        384 => FlashSize::Sz384K,
+        512 => FlashSize::Sz512K,
+        768 => FlashSize::Sz768K,
+        1024 => FlashSize::Sz1M,
+        _ => panic!("flash size is not supported by the HAL"),
+    }
+}
+
+#[cfg(not(feature = "stm32f103"))]
+const fn flash_size(bytes: u32) -> FlashSize {
+    bytes
+}
+
 /// EEPROM-capable peripheral.
 pub trait EEPROMExt
 where
@@ -109,6 +622,21 @@ pub trait Flash {
     /// a location in the Flash memory aligned to two bytes.
     fn read(&mut self, params: &Params, offset: u32) -> FlashResult<HalfWord>;
 
+    /// Read consecutive half-words starting at a specified address into `data`. Used for
+    /// scanning pages; default implementation reads half-words one by one, implementations for
+    /// memory-mapped flash should override it with plain memory loads.
+    fn read_slice(
+        &mut self,
+        params: &Params,
+        offset: u32,
+        data: &mut [HalfWord],
+    ) -> FlashResult<()> {
+        for (i, value) in data.iter_mut().enumerate() {
+            *value = self.read(params, offset + 2 * i as u32)?;
+        }
+        Ok(())
+    }
+
     /// Write half-word (16-bit) value at a specified address. `address` must be an address of
     /// a location in the Flash memory aligned to two bytes.
     fn write(&mut self, params: &Params, offset: u32, data: u16) -> FlashResult<()>;
@@ -116,87 +644,292 @@ pub trait Flash {
     /// Erase specified flash page. `address` must be an address of a beginning of the page in
     /// Flash memory.
     fn page_erase(&mut self, params: &Params, address: u32) -> FlashResult<()>;
+
+    /// Check if a programmed half-word could be programmed again to clear more of its bits. NOR
+    /// flash supports that, while STM32 only allows programming zero over a programmed half-word.
+    fn clears_bits(&self) -> bool {
+        false
+    }
+}
+
+/// Low-level trait used by `EEPROMReader` to read flash memory. Implemented for every `Flash`.
+pub trait ReadFlash {
+    /// Read half-word (16-bit) value at a specified address. `address` must be an address of
+    /// a location in the Flash memory aligned to two bytes.
+    fn read(&mut self, params: &Params, offset: u32) -> FlashResult<HalfWord>;
+}
+
+impl<F> ReadFlash for F
+where
+    F: Flash,
+{
+    fn read(&mut self, params: &Params, offset: u32) -> FlashResult<HalfWord> {
+        Flash::read(self, params, offset)
+    }
 }
 
 #[cfg(feature = "stm32f103")]
-impl<'a> EEPROMExt for &'a mut Parts {
+impl EEPROMExt for &mut Parts {
     fn eeprom(self, params: Params) -> EEPROM<Self> {
         EEPROM::new(params, self)
     }
 }
 
 #[cfg(feature = "stm32f103")]
-impl<'a> Flash for &'a mut Parts {
+impl Flash for &mut Parts {
     fn read(&mut self, params: &Params, address: u32) -> FlashResult<HalfWord> {
-        let writer = self.writer(params.page_size, params.flash_size);
+        let writer = writer(self, params);
         let data = writer.read(address, 2)?;
         Ok(u16::from_le_bytes([data[0], data[1]]))
     }
 
+    fn read_slice(
+        &mut self,
+        params: &Params,
+        address: u32,
+        data: &mut [HalfWord],
+    ) -> FlashResult<()> {
+        // Flash is memory-mapped, so the whole slice is read with plain memory loads
+        let writer = writer(self, params);
+        let bytes = writer.read(address, data.len() * 2)?;
+        for (value, bytes) in data.iter_mut().zip(bytes.chunks_exact(2)) {
+            *value = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        Ok(())
+    }
+
     fn write(&mut self, params: &Params, address: u32, data: HalfWord) -> FlashResult<()> {
-        let mut writer = self.writer(params.page_size, params.flash_size);
+        let mut writer = writer(self, params);
         writer.write(address, &data.to_le_bytes())?;
         Ok(())
     }
 
     fn page_erase(&mut self, params: &Params, address: u32) -> FlashResult<()> {
-        let mut writer = self.writer(params.page_size, params.flash_size);
+        let mut writer = writer(self, params);
         writer.page_erase(address)?;
         Ok(())
     }
 }
 
 /// EEPROM controller. Uses Flash for implementing key-value storage for 16-bit data values.
-pub struct EEPROM<F> {
-    params: Params,
-    // Amount of items per page (full words)
-    page_items: u32,
+pub struct EEPROM<F, L = Params> {
+    layout: L,
     flash: F,
+    // Incremental compaction in progress (see `compact_step`)
+    compaction: Option<Compaction>,
+    // Amount of distinct tags stored, counted lazily
+    live_tags: Option<u32>,
+    // Read back every programmed half-word and erased page
+    verify: bool,
+    diagnostics: Diagnostics,
+}
+
+// State of the incremental compaction
+#[derive(Clone, Copy, Debug)]
+struct Compaction {
+    // Page being compacted (active page)
+    source: u32,
+    // Next item of the source page to process
+    next_item: u32,
+    // Page the values are copied to
+    target: u32,
+    // Next free item of the target page
+    target_pos: u32,
 }
 
 impl<F> EEPROM<F>
 where
     F: Flash,
 {
-    /// Create new EEPROM controller.
+    /// Create new EEPROM controller, validating the parameters first.
+    pub fn try_new(params: Params, flash: F) -> Result<Self, ParamsError> {
+        params.validate()?;
+        Ok(EEPROM::new(params, flash))
+    }
+
+    /// Create new EEPROM controller. Parameters are not validated, see `try_new`.
     pub fn new(params: Params, flash: F) -> Self {
+        EEPROM::with_layout(params, flash)
+    }
+
+    /// Move values stored in the EEPROM region described by `old` parameters to the region
+    /// described by `new` parameters (for example, when firmware update changes the amount of
+    /// pages or their location). Regions are allowed to overlap. Controller is configured with
+    /// `new` parameters afterwards and is initialized (no need to call `init`).
+    ///
+    /// Migration is power-safe: the new region becomes active only after all values are copied,
+    /// and pages of the old region are erased after that. Calling this function again after
+    /// an interruption (or after migration is complete) finishes the migration.
+    ///
+    /// Returns `Error::InvalidParams` if `new` parameters are not valid, have a different page
+    /// size than `old` ones, or if the new region has no usable page besides the active page of
+    /// the old region. Flash is not modified in that case.
+    pub fn migrate_layout(&mut self, old: Params, new: Params) -> EEPROMResult<()> {
+        new.validate()?;
+        if old.page_size != new.page_size {
+            return Err(Error::InvalidParams(ParamsError::PageSizeMismatch));
+        }
+
+        self.configure(new);
+        let mut target = self.find_valid_active().map(|page| new.first_page + page);
+        if target.is_none() {
+            self.configure(old);
+            let source = match self.resolve_active()? {
+                Some(page) => Some((page, HEADER_ITEMS, self.page_generation(page))),
+                // Legacy page header is a single item
+                None => self.find_legacy().map(|page| (page, 1, 0)),
+            };
+            if let Some((src_page, first_item, generation)) = source {
+                target = Some(self.migrate_to(old, src_page, first_item, generation, new)?);
+            }
+        }
+
+        // Erase old region, except the page which is now active in the new region and retired
+        // pages (so they are still skipped if regions overlap)
+        self.configure(old);
+        for page in 0..old.page_count {
+            if Some(old.first_page + page) != target && !self.is_bad_page(page) {
+                self.erase_page(page)?;
+            }
+        }
+
+        self.configure(new);
+        self.init()
+    }
+
+    // Copy values from the active page of the `old` region to a page of the `new` region, which
+    // is not occupied by the source page, and mark it as active. Returns absolute index of the
+    // target page.
+    fn migrate_to(
+        &mut self,
+        old: Params,
+        src_page: u32,
+        first_item: u32,
+        generation: u32,
+        new: Params,
+    ) -> EEPROMResult<u32> {
+        let src_abs = old.first_page + src_page;
+
+        // Use parameters spanning both regions, so pages of both could be addressed
+        let first_page = old.first_page.min(new.first_page);
+        let last_page = (old.first_page + old.page_count).max(new.first_page + new.page_count);
+        self.configure(Params {
+            first_page,
+            page_count: last_page - first_page,
+            ..new
+        });
+        let target = (new.first_page..new.first_page + new.page_count)
+            .find(|&page| page != src_abs && !self.is_bad_page(page - first_page));
+        let tgt_abs = match target {
+            Some(page) => page,
+            None => {
+                self.configure(old);
+                return Err(Error::InvalidParams(ParamsError::TooFewPages));
+            }
+        };
+        self.erase_page(tgt_abs - first_page)?;
+        let items = first_item..self.layout.page_items();
+        self.copy_items(src_abs - first_page, items, tgt_abs - first_page)?;
+
+        self.configure(new);
+        self.activate_page(tgt_abs - new.first_page, generation.wrapping_add(1))?;
+        Ok(tgt_abs)
+    }
+
+    fn configure(&mut self, params: Params) {
+        self.layout = params;
+        self.compaction = None;
+        self.live_tags = None;
+    }
+}
+
+impl<F, L> EEPROM<F, L>
+where
+    F: Flash,
+    L: Layout,
+{
+    /// Create new EEPROM controller with the given layout.
+    pub fn with_layout(layout: L, flash: F) -> Self {
         EEPROM {
-            params,
-            page_items: (params.page_size as u32) * 1024 / ITEM_SIZE,
+            layout,
             flash,
+            compaction: None,
+            live_tags: None,
+            verify: false,
+            diagnostics: Diagnostics::default(),
         }
     }
 
+    /// Enable read-back verification (disabled by default). Every programmed half-word is read
+    /// back and every erased page is checked to be blank. Erase is retried if the page is not
+    /// blank. Item which is read back with different data or tag is marked invalid and the value
+    /// is written to the next free slot instead; `Error::Verify` is returned if it keeps failing,
+    /// and `Error::Corrupted` if the item cannot be marked invalid.
+    /// With more than two pages, page which keeps failing to erase or program is retired instead.
+    pub fn set_verify(&mut self, verify: bool) {
+        self.verify = verify;
+    }
+
+    /// Counters of the failures detected in the verify mode.
+    pub fn diagnostics(&self) -> Diagnostics {
+        self.diagnostics
+    }
+
     /// Initialize EEPROM controller. Checks that all internal data structures are in consistent
     /// state and fixes them otherwise.
-    pub fn init(&mut self) -> FlashResult<()> {
-        let active = self.find_active();
-        for page in 0..self.params.page_count {
-            match active {
-                Some(p) if p == page => (), // Do not erase active page
-                _ => {
-                    self.erase_page(page)?;
-                }
+    ///
+    /// Returns an error without modifying the flash if the active page is written in an unknown
+    /// or incompatible format. Use `check` to inspect the flash before running `init`.
+    pub fn init(&mut self) -> EEPROMResult<()> {
+        self.live_tags = None;
+        let mut active = self.resolve_active()?;
+        if active.is_none() {
+            if let Some(legacy) = self.find_legacy() {
+                active = Some(self.migrate_legacy(legacy)?);
+            }
+        }
+        if let Some(page) = active {
+            if self.open_transaction(page).is_some() {
+                active = Some(self.compact_page(page)?);
+            }
+        }
+
+        for page in 0..self.layout.params().page_count {
+            if active == Some(page) || self.is_bad_page(page) {
+                continue; // Do not erase active and retired pages
+            }
+            match self.erase_page(page) {
+                Err(Error::Verify) if self.retire_page(page)? => (),
+                result => result?,
             }
         }
 
         if active.is_none() {
-            // Active page not found, mark the first page as active
-            return self.set_page_status(0, ACTIVE_PAGE_MARKER);
+            // Active page not found, mark the first usable page as active
+            let page = self.first_usable_page();
+            self.activate_page(page, 0)?;
         }
         Ok(())
     }
 
     /// Erase all values stored in EEPROM
-    pub fn erase(&mut self) -> FlashResult<()> {
-        for page in 0..self.params.page_count {
-            let start_offset =
-                (self.params.first_page + page) * (self.params.page_size as u32) * 1024;
-            self.flash.page_erase(&self.params, start_offset)?;
+    pub fn erase(&mut self) -> EEPROMResult<()> {
+        self.compaction = None;
+        self.live_tags = None;
+        for page in 0..self.layout.params().page_count {
+            if self.is_bad_page(page) {
+                continue;
+            }
+            match self.erase_verified(page) {
+                Err(Error::Verify) if self.retire_page(page)? => (),
+                result => result?,
+            }
         }
 
-        // Mark the first page as the active
-        self.set_page_status(0, ACTIVE_PAGE_MARKER)
+        // Mark the first usable page as the active
+        let page = self.first_usable_page();
+        self.activate_page(page, 0)?;
+        Ok(())
     }
 
     /// Read value for a specified tag
@@ -208,91 +941,939 @@ where
         assert_eq!(tag & 0b1000_0000_0000_0000, 0, "msb bit of `1` is reserved");
 
         let page = self.find_active().expect("cannot find active page");
-        self.search(page, self.page_items, tag)
+        self.search(page, self.layout.page_items(), tag)
     }
 
-    /// Write value for a specified tag.
+    /// Write value for a specified tag. Returns `Error::CapacityExceeded` if `tag` is not stored
+    /// yet and there is no space for it (see `capacity`).
     ///
     /// # Panics
     /// * panics if active page cannot be found
     /// * panics if page is full even after compacting it to the empty one
     /// * panics if tag value has the most significant bit set to `1` (reserved value)
-    pub fn write(&mut self, tag: HalfWord, data: HalfWord) -> FlashResult<()> {
+    pub fn write(&mut self, tag: HalfWord, data: HalfWord) -> EEPROMResult<()> {
         assert_eq!(tag & 0b1000_0000_0000_0000, 0, "msb bit of `1` is reserved");
 
         let page = self.find_active().expect("cannot find active page");
+        let current = self.search(page, self.layout.page_items(), tag);
+        self.write_item(page, tag, data, current.is_none())
+    }
 
-        // rescue all the data to the free page first
-        let page = self.rescue_if_full(page)?;
+    /// Write value for a specified tag only if it is different from the stored one, saving
+    /// flash endurance. Returns `true` if value was written.
+    ///
+    /// # Panics
+    /// * panics if active page cannot be found
+    /// * panics if page is full even after compacting it to the empty one
+    /// * panics if tag value has the most significant bit set to `1` (reserved value)
+    pub fn update(&mut self, tag: HalfWord, data: HalfWord) -> EEPROMResult<bool> {
+        assert_eq!(tag & 0b1000_0000_0000_0000, 0, "msb bit of `1` is reserved");
 
-        for item in 1..self.page_items {
-            if self.read_item(page, item) == ERASED_ITEM {
-                return self.program_item(page, item, tag, data);
-            }
+        let page = self.find_active().expect("cannot find active page");
+        let current = self.search(page, self.layout.page_items(), tag);
+        if current == Some(data) {
+            return Ok(false);
         }
-        panic!("too many variables");
+        self.write_item(page, tag, data, current.is_none())?;
+        Ok(true)
     }
 
-    fn rescue_if_full(&mut self, src_page: u32) -> Result<u32, FlashError> {
-        // Check if last word of the page was written or not
-        // Note that we check both data and the tag as in case of failure we might write
-        // data, but not the tag.
-        if self.read_item(src_page, self.page_items - 1) == ERASED_ITEM {
-            // Page is not full yet -- last item is an erased value
-            return Ok(src_page);
-        }
-
-        // Last word was not 0xffffffff, we need to rescue to the next page
-
-        // Target page
-        let tgt_page = if src_page == self.params.page_count - 1 {
-            0
-        } else {
-            src_page + 1
-        };
-        let mut tgt_pos = 1; // skip page marker item
-
-        // Start scanning source page from the end (to get the latest value)
-        for item in (1..self.page_items).rev() {
-            let (tag, data) = self.read_item_tuple(src_page, item);
-            if tag == 0xffff {
-                continue; // empty value -- skip
-            }
+    fn write_item(
+        &mut self,
+        page: u32,
+        tag: HalfWord,
+        data: HalfWord,
+        new_tag: bool,
+    ) -> EEPROMResult<()> {
+        self.reserve_items(page, u32::from(new_tag), 1)?;
 
-            if self.search(tgt_page, tgt_pos, tag).is_none() {
-                self.program_item(tgt_page, tgt_pos, tag, data)?;
-                tgt_pos += 1;
+        // rescue all the data to the free page first
+        let page = self.rescue_if_full(page)?;
+        match self.append(page, tag, data) {
+            // Page which keeps failing to program is retired once its values are compacted
+            Err(Error::Verify) if self.can_retire() => {
+                let next = self.compact_page(page)?;
+                self.retire_page(page)?;
+                self.append(next, tag, data)?;
             }
+            result => result?,
         }
-
-        self.set_page_status(tgt_page, ACTIVE_PAGE_MARKER)?; // Mark target page as active
-        self.erase_page(src_page)?; // Erase the source page
-
-        Ok(tgt_page)
+        self.add_tag(new_tag);
+        Ok(())
     }
 
-    fn search(&mut self, page: u32, max_item: u32, tag: HalfWord) -> Option<HalfWord> {
-        for item in (1..max_item).rev() {
-            let (t, data) = self.read_item_tuple(page, item);
-            if t == tag {
-                return Some(data);
-            }
+    /// Write value for a specified tag, unless the active page is full. Never compacts or erases
+    /// pages, returning `Error::WouldCompact` instead, so the application could call `compact`
+    /// when it can afford it.
+    ///
+    /// # Panics
+    /// * panics if active page cannot be found
+    /// * panics if tag value has the most significant bit set to `1` (reserved value)
+    pub fn try_write(&mut self, tag: HalfWord, data: HalfWord) -> EEPROMResult<()> {
+        assert_eq!(tag & 0b1000_0000_0000_0000, 0, "msb bit of `1` is reserved");
+
+        let page = self.find_active().expect("cannot find active page");
+        let new_tag = self.search(page, self.layout.page_items(), tag).is_none();
+        self.reserve_items(page, u32::from(new_tag), 1)?;
+        if self.is_page_full(page) {
+            return Err(Error::WouldCompact);
         }
-        None
+        self.append(page, tag, data)?;
+        self.add_tag(new_tag);
+        Ok(())
     }
 
-    fn find_active(&mut self) -> Option<u32> {
-        (0..self.params.page_count).find(|&page| self.page_status(page) == ACTIVE_PAGE_MARKER)
-    }
+    /// Clear given bits of the value for a specified tag (missing value is treated as `0xFFFF`),
+    /// returning the updated value. Suitable for one-shot flags or unary-encoded counters, as
+    /// updates which only clear bits are programmed in place of the stored value instead of
+    /// consuming a new item, if the flash supports it (see `Flash::clears_bits`) and the verify
+    /// mode is disabled. Interrupted update leaves each bit either cleared or intact.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use eeprom::{EEPROM, Flash};
+    /// # fn example<F: Flash>(eeprom: &mut EEPROM<F>) {
+    /// const FLAGS: u16 = 3;
+    /// const CALIBRATED: u16 = 0b01;
+    /// const PROVISIONED: u16 = 0b10;
+    ///
+    /// // Flag is raised once its bit is cleared
+    /// eeprom.clear_bits(FLAGS, CALIBRATED).unwrap();
+    /// let flags = eeprom.clear_bits(FLAGS, PROVISIONED).unwrap();
+    /// assert_eq!(0, flags & (CALIBRATED | PROVISIONED));
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    /// * panics if active page cannot be found
+    /// * panics if page is full even after compacting it to the empty one
+    /// * panics if tag value has the most significant bit set to `1` (reserved value)
+    pub fn clear_bits(&mut self, tag: HalfWord, bits: HalfWord) -> EEPROMResult<HalfWord> {
+        assert_eq!(tag & 0b1000_0000_0000_0000, 0, "msb bit of `1` is reserved");
 
-    fn page_status(&mut self, page: u32) -> HalfWord {
+        let page = self.find_active().expect("cannot find active page");
+        let current = self.find_item(page, self.layout.page_items(), tag);
+        let data = current.map_or(0xffff, |(_, data)| data) & !bits;
+        match current {
+            Some((_, value)) if value == data => (),
+            Some((item, _)) if self.is_programmable(page, item, data) => {
+                let item_addr = self.item_offset(page, item);
+                self.program_half_word(item_addr + 2, data)?;
+            }
+            _ => self.write_item(page, tag, data, current.is_none())?,
+        }
+        Ok(data)
+    }
+
+    // Check if the value of the item could be replaced with `data` (which only clears bits) in
+    // place
+    fn is_programmable(&self, page: u32, item: u32, data: HalfWord) -> bool {
+        // Item might be already copied by the incremental compaction
+        let copied = matches!(self.compaction, Some(state) if state.source == page && item < state.next_item);
+        // Value programmed in place could not be marked invalid if it fails to verify
+        (data == 0 || self.flash.clears_bits()) && !copied && !self.verify
+    }
+
+    /// Read value of the counter for a specified tag (see `increment`). Returns `0` if tag is not
+    /// stored yet.
+    ///
+    /// # Panics
+    /// * panics if active page cannot be found
+    /// * panics if tag value has the most significant bit set to `1` (reserved value)
+    pub fn counter(&mut self, tag: HalfWord) -> u32 {
+        assert_eq!(tag & 0b1000_0000_0000_0000, 0, "msb bit of `1` is reserved");
+
+        let page = self.find_active().expect("cannot find active page");
+        match self.find_item(page, self.layout.page_items(), tag) {
+            Some((item, data)) => self.counter_value(page, item, tag, data),
+            None => 0,
+        }
+    }
+
+    /// Increment 32-bit counter for a specified tag, returning the new value. Counter is never
+    /// observed to go backwards: if increment is interrupted by a reset, either the old or the new
+    /// value is read afterwards. Value written by `write` is used as the initial counter value.
+    ///
+    /// Counter takes two slots of the `capacity`: the lower half of the counter is stored as the
+    /// value of the tag (see `read`), and the upper half is stored in a separate record. Each
+    /// increment writes a single item, like `write` does, except for the first one and the ones
+    /// carrying over to the upper half, which write the record as well. So the counter takes no
+    /// less flash than writing the incremented value would: the gain over read-increment-write is
+    /// that all 32 bits are updated atomically. Value written by `write` afterwards replaces the
+    /// lower half of the counter.
+    ///
+    /// # Panics
+    /// * panics if active page cannot be found
+    /// * panics if counter overflows
+    /// * panics if tag value has the most significant bit set to `1` (reserved value)
+    pub fn increment(&mut self, tag: HalfWord) -> EEPROMResult<u32> {
+        assert_eq!(tag & 0b1000_0000_0000_0000, 0, "msb bit of `1` is reserved");
+
+        let mut page = self.find_active().expect("cannot find active page");
+        let (value, high, new_slots) = match self.find_item(page, self.layout.page_items(), tag) {
+            Some((item, data)) => match self.counter_high(page, item, tag) {
+                Some(high) => ((u32::from(high) << 16) + u32::from(data), Some(high), 0),
+                None => (u32::from(data), None, 1),
+            },
+            None => (0, None, 2),
+        };
+        let value = value.checked_add(1).expect("counter overflow");
+
+        if high == Some((value >> 16) as HalfWord) {
+            self.write_item(page, tag, value as HalfWord, false)?;
+            return Ok(value);
+        }
+
+        self.reserve_items(page, new_slots, 2)?;
+        let page_items = self.layout.page_items();
+        let mut pos = self.first_free_item(page);
+        if pos + 2 > page_items {
+            page = self.compact_page(page)?;
+            pos = self.first_free_item(page);
+        }
+        self.program_counter(page, pos, tag, value)?;
+        self.live_tags = self.live_tags.map(|live| live + new_slots);
+        Ok(value)
+    }
+
+    fn program_counter(
+        &mut self,
+        page: u32,
+        pos: u32,
+        tag: HalfWord,
+        value: u32,
+    ) -> EEPROMResult<()> {
+        let item_addr = self.item_offset(page, pos);
+
+        // Tag of the value is written last, so if we fail, the record is never preceded by
+        // a value and the counter keeps the old value.
+        self.program_data(item_addr, value as HalfWord)?;
+        self.program_item(page, pos + 1, COUNTER_TAG, (value >> 16) as HalfWord)?;
+        self.program_tag(item_addr, tag)
+    }
+
+    // Value of the counter stored in the item
+    fn counter_value(&mut self, page: u32, item: u32, tag: HalfWord, data: HalfWord) -> u32 {
+        match self.counter_high(page, item, tag) {
+            Some(high) => (u32::from(high) << 16) + u32::from(data),
+            // Value written by `write`
+            None => u32::from(data),
+        }
+    }
+
+    // Upper half of the counter the item belongs to, if any
+    fn counter_high(&mut self, page: u32, item: u32, tag: HalfWord) -> Option<HalfWord> {
+        let anchor = self.counter_anchor(page, item, tag)?;
+        Some(self.read_item_tuple(page, anchor + 1).1)
+    }
+
+    // Latest item of the `tag` up to the given one, which is followed by a counter record
+    fn counter_anchor(&mut self, page: u32, item: u32, tag: HalfWord) -> Option<u32> {
+        let mut end = item + 1;
+        while let Some((anchor, _)) = self.find_item(page, end, tag) {
+            if anchor + 1 < self.layout.page_items()
+                && is_counter_record(self.read_item_tuple(page, anchor + 1).0)
+            {
+                return Some(anchor);
+            }
+            end = anchor;
+        }
+        None
+    }
+
+    /// Write multiple values atomically. Values written by `build` are stored either all together
+    /// or, if writing them is interrupted by a reset, not at all (incomplete transaction is
+    /// discarded by `init`). Returns `Error::CapacityExceeded` if values would not fit into the
+    /// page even after compaction.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use eeprom::{EEPROM, Flash};
+    /// # fn example<F: Flash>(eeprom: &mut EEPROM<F>) {
+    /// // Calibration gain and offset always change together
+    /// eeprom
+    ///     .transaction(|tx| {
+    ///         tx.write(1, 0x0123);
+    ///         tx.write(2, 0x4567);
+    ///     })
+    ///     .unwrap();
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    /// * panics if active page cannot be found
+    /// * see `Transaction::write`
+    pub fn transaction<B>(&mut self, build: B) -> EEPROMResult<()>
+    where
+        B: FnOnce(&mut Transaction),
+    {
+        let mut tx = Transaction::new();
+        build(&mut tx);
+        let entries = tx.entries();
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let count = entries.len() as u32;
+
+        let mut page = self.find_active().expect("cannot find active page");
+        let mut new_tags = 0;
+        for &(tag, _) in entries {
+            if self.search(page, self.layout.page_items(), tag).is_none() {
+                new_tags += 1;
+            }
+        }
+
+        // Transaction record and all the values should fit into the page after compaction
+        self.reserve_items(page, new_tags, count + 1)?;
+        let page_items = self.layout.page_items();
+        let mut pos = self.first_free_item(page);
+        if pos + count + 1 > page_items {
+            page = self.compact_page(page)?;
+            pos = self.first_free_item(page);
+        }
+
+        if let Err(err) = self.program_transaction(page, pos, entries) {
+            // Discard values written so far, so the incomplete transaction is never visible
+            self.compact_page(page)?;
+            return Err(err);
+        }
+        self.live_tags = self.live_tags.map(|live| live + new_tags);
+        Ok(())
+    }
+
+    fn program_transaction(
+        &mut self,
+        page: u32,
+        pos: u32,
+        entries: &[(HalfWord, HalfWord)],
+    ) -> EEPROMResult<()> {
+        self.program_item(page, pos, TRANSACTION_TAG, entries.len() as HalfWord)?;
+        for (item, &(tag, data)) in (pos + 1..).zip(entries) {
+            self.program_item(page, item, tag, data)?;
+        }
+        Ok(())
+    }
+
+    // Find the record of the last transaction written to the page, if not all of its values are
+    // written
+    fn open_transaction(&mut self, page: u32) -> Option<u32> {
+        self.pages().open_transaction(page).unwrap()
+    }
+
+    /// Maximum amount of distinct tags that could be stored (counters take two slots, see
+    /// `increment`). Two items of the page are reserved, so there is always space for writing
+    /// a value or a counter after the page is compacted.
+    pub fn capacity(&self) -> u32 {
+        page_capacity(self.layout.page_items())
+    }
+
+    /// Amount of distinct tags stored (counters take two slots).
+    ///
+    /// # Panics
+    /// * panics if active page cannot be found
+    pub fn live_tags(&mut self) -> u32 {
+        let page = self.find_active().expect("cannot find active page");
+        self.count_live_tags(page)
+    }
+
+    /// Amount of new distinct tags that could still be stored.
+    ///
+    /// # Panics
+    /// * panics if active page cannot be found
+    pub fn free_slots(&mut self) -> u32 {
+        self.capacity().saturating_sub(self.live_tags())
+    }
+
+    // Check that `items` items taking `new_slots` more slots of the capacity could be written to
+    // the page, compacting it first if needed
+    fn reserve_items(&mut self, page: u32, new_slots: u32, items: u32) -> EEPROMResult<()> {
+        let live = self.count_live_tags(page);
+        if live + new_slots > self.capacity() || live + items > self.capacity() + RESERVED_ITEMS {
+            return Err(Error::CapacityExceeded);
+        }
+        Ok(())
+    }
+
+    fn add_tag(&mut self, new_tag: bool) {
+        if new_tag {
+            self.live_tags = self.live_tags.map(|count| count + 1);
+        }
+    }
+
+    fn count_live_tags(&mut self, page: u32) -> u32 {
+        if let Some(count) = self.live_tags {
+            return count;
+        }
+
+        let mut buf = [0; SCAN_ITEMS as usize * 2];
+        let end = self.first_free_item(page);
+        let mut first = HEADER_ITEMS;
+        let mut count = 0;
+        while first < end {
+            let chunk_items = SCAN_ITEMS.min(end - first);
+            let chunk = &mut buf[..chunk_items as usize * 2];
+            self.read_items(page, first, chunk);
+            for (i, &tag) in chunk.iter().step_by(2).enumerate() {
+                let item = first + i as u32;
+                if is_value_tag(tag) && !self.is_overwritten(page, item, tag) {
+                    count += 1;
+                    if self.counter_anchor(page, item, tag).is_some() {
+                        count += 1;
+                    }
+                }
+            }
+            first += chunk_items;
+        }
+        self.live_tags = Some(count);
+        count
+    }
+
+    /// Check if the active page is full, so the next `write` would compact it.
+    ///
+    /// # Panics
+    /// * panics if active page cannot be found
+    pub fn needs_compaction(&mut self) -> bool {
+        let page = self.find_active().expect("cannot find active page");
+        self.is_page_full(page)
+    }
+
+    /// Scan all pages without modifying the flash and report their consistency, so it could be
+    /// decided whether to run `init` (which erases pages it does not recognize).
+    pub fn check(&mut self) -> Report {
+        let (active, error) = match self.resolve_active() {
+            Ok(active) => (active, None),
+            Err(err) => (None, Some(err)),
+        };
+        let mut report = Report {
+            active,
+            error,
+            active_pages: 0,
+            retired_pages: 0,
+            unknown_pages: 0,
+            estimated_erases: None,
+            live_items: 0,
+            stale_items: 0,
+            erased_items: 0,
+            corrupt_items: 0,
+            trailing_items: 0,
+        };
+        for page in 0..self.layout.params().page_count {
+            let page_report = self.check_page(page);
+            match page_report.state {
+                PageState::Active | PageState::Legacy => report.active_pages += 1,
+                PageState::Retired => report.retired_pages += 1,
+                PageState::Unknown => report.unknown_pages += 1,
+                _ => (),
+            }
+            report.live_items += page_report.live_items;
+            report.stale_items += page_report.stale_items;
+            report.erased_items += page_report.erased_items;
+            report.corrupt_items += page_report.corrupt_items;
+            report.trailing_items += page_report.trailing_items;
+        }
+        let usable = self.layout.params().page_count - report.retired_pages;
+        report.estimated_erases = active.map(|page| self.page_generation(page).div_ceil(usable));
+        report
+    }
+
+    /// Scan the page without modifying the flash and report its state and the amount of items of
+    /// each kind (see `check`).
+    ///
+    /// # Panics
+    /// * panics if page is out of range
+    pub fn check_page(&mut self, page: u32) -> PageReport {
+        assert!(
+            page < self.layout.params().page_count,
+            "page must be less than the amount of pages"
+        );
+        let status = self.page_status(page);
+        let magic = self.read_header(page, HEADER_MAGIC);
+        let state = if self.is_bad_page(page) {
+            PageState::Retired
+        } else if !self.is_page_dirty(page) {
+            PageState::Erased
+        } else if self.is_legacy_page(page) {
+            PageState::Legacy
+        } else if status == ACTIVE_PAGE_MARKER {
+            PageState::Active
+        } else if status == OBSOLETE_PAGE_MARKER && magic == FORMAT_MAGIC {
+            PageState::Obsolete
+        } else if status == 0xffff {
+            PageState::Incomplete
+        } else {
+            PageState::Unknown
+        };
+        let current = matches!(state, PageState::Active | PageState::Obsolete)
+            && magic == FORMAT_MAGIC
+            && self.read_header(page, HEADER_VERSION) == FORMAT_VERSION;
+        let mut report = PageReport {
+            state,
+            generation: current.then(|| self.page_generation(page)),
+            live_items: 0,
+            stale_items: 0,
+            erased_items: 0,
+            corrupt_items: 0,
+            trailing_items: 0,
+        };
+        if matches!(state, PageState::Retired | PageState::Unknown) {
+            return report;
+        }
+
+        // Legacy page header is a single item
+        let first = if state == PageState::Legacy {
+            1
+        } else {
+            HEADER_ITEMS
+        };
+        let live = matches!(state, PageState::Active | PageState::Legacy);
+        let page_items = self.layout.page_items();
+        let discarded = self.open_transaction(page).unwrap_or(page_items);
+        let mut erased = false;
+        for item in first..page_items {
+            if self.read_item(page, item) == ERASED_ITEM {
+                report.erased_items += 1;
+                erased = true;
+                continue;
+            }
+            if erased {
+                report.trailing_items += 1;
+            }
+
+            let (tag, _) = self.read_item_tuple(page, item);
+            if is_value_tag(tag) {
+                if live && item < discarded && !self.is_overwritten(page, item, tag) {
+                    report.live_items += 1;
+                } else {
+                    report.stale_items += 1;
+                }
+            } else if is_counter_record(tag)
+                && item > first
+                && is_value_tag(self.read_item_tuple(page, item - 1).0)
+            {
+                // Counter record is live if the latest value of the counter belongs to it
+                let counter = self.read_item_tuple(page, item - 1).0;
+                let current = match self.find_item(page, page_items, counter) {
+                    Some((latest, _)) if live && latest < discarded => {
+                        self.counter_anchor(page, latest, counter) == Some(item - 1)
+                    }
+                    _ => false,
+                };
+                if current {
+                    report.live_items += 1;
+                } else {
+                    report.stale_items += 1;
+                }
+            } else if tag == TRANSACTION_TAG || is_counter_record(tag) {
+                // Committed transaction, or increment interrupted before the value tag was written
+                report.stale_items += 1;
+            } else {
+                report.corrupt_items += 1;
+            }
+        }
+        report
+    }
+
+    // Write item to the slot following the last written one, so items are always ordered by the
+    // time they are written (erased slot left by an interrupted write is never filled later). In
+    // the verify mode, item which was not programmed correctly is skipped and the next slot is
+    // tried.
+    fn append(&mut self, page: u32, tag: HalfWord, data: HalfWord) -> EEPROMResult<()> {
+        let mut retries = 0;
+        for item in self.first_free_item(page)..self.layout.page_items() {
+            match self.program_item(page, item, tag, data) {
+                Err(Error::Verify) if retries < PROGRAM_RETRIES => retries += 1,
+                result => return result,
+            }
+        }
+        if retries != 0 {
+            return Err(Error::Verify);
+        }
+        panic!("too many variables");
+    }
+
+    /// Compact values to the next page, leaving only the latest value for every tag. Happens
+    /// automatically during `write` when the active page is full.
+    ///
+    /// Compacted page is marked as obsolete rather than erased, so it should be erased by
+    /// `prepare` (or `init`) before the next compaction. Otherwise, compaction erases the target
+    /// page itself.
+    ///
+    /// # Panics
+    /// * panics if active page cannot be found
+    pub fn compact(&mut self) -> EEPROMResult<()> {
+        let page = self.find_active().expect("cannot find active page");
+        self.compact_page(page)?;
+        Ok(())
+    }
+
+    /// Erase the page next to the active one, so it is ready to be used as a target of the next
+    /// compaction. Should be called when the application can afford blocking for the duration of
+    /// the page erase, so `write` never needs to erase pages during compaction.
+    ///
+    /// # Panics
+    /// * panics if active page cannot be found
+    pub fn prepare(&mut self) -> EEPROMResult<()> {
+        let page = self.find_active().expect("cannot find active page");
+        if matches!(self.compaction, Some(state) if state.source == page) {
+            // Spare page is being filled by the incremental compaction
+            return Ok(());
+        }
+        self.erase_target(page)?;
+        Ok(())
+    }
+
+    /// Perform a bounded step of the incremental compaction, processing at most `max_items`
+    /// items of the active page. Returns `true` once compaction is complete and the next page
+    /// became active.
+    ///
+    /// Values could be read and written in between the steps: values written to the active page
+    /// in the meantime are picked up by the subsequent steps. Compaction is power-safe, as the
+    /// target page becomes active only at the last step. The first step erases the target page
+    /// if it was not erased by `prepare` beforehand.
+    ///
+    /// If the active page is compacted by other means in between (for example, `write` to
+    /// a full page), compaction starts over. If the target page becomes full because of the
+    /// values written in the meantime, the step falls back to the full compaction (`compact`).
+    ///
+    /// # Panics
+    /// * panics if active page cannot be found
+    pub fn compact_step(&mut self, max_items: u32) -> EEPROMResult<bool> {
+        let source = self.find_active().expect("cannot find active page");
+        let mut state = match self.compaction {
+            Some(state) if state.source == source => state,
+            _ => Compaction {
+                source,
+                next_item: HEADER_ITEMS,
+                target: self.erase_target(source)?,
+                target_pos: HEADER_ITEMS,
+            },
+        };
+        let target = state.target;
+        self.compaction = None;
+
+        let page_items = self.layout.page_items();
+        for _ in 0..max_items {
+            if state.next_item == page_items
+                || self.read_item(source, state.next_item) == ERASED_ITEM
+            {
+                let generation = self.page_generation(source).wrapping_add(1);
+                self.activate_page(target, generation)?;
+                self.write_header(source, HEADER_STATUS, OBSOLETE_PAGE_MARKER)?;
+                return Ok(true);
+            }
+
+            // Scan forward, copying values which are not overwritten later on the source page
+            let (tag, data) = self.read_item_tuple(source, state.next_item);
+            if is_value_tag(tag) && !self.is_overwritten(source, state.next_item, tag) {
+                let high = self.counter_high(source, state.next_item, tag);
+                if state.target_pos + copy_slots(high) > page_items {
+                    self.compact_page(source)?;
+                    return Ok(true);
+                }
+                state.target_pos = self.copy_value(target, state.target_pos, tag, data, high)?;
+            }
+            state.next_item += 1;
+        }
+
+        self.compaction = Some(state);
+        Ok(false)
+    }
+
+    // Check if there is a newer value for the `tag` after the given item
+    fn is_overwritten(&mut self, page: u32, item: u32, tag: HalfWord) -> bool {
+        let mut buf = [0; SCAN_ITEMS as usize * 2];
+        let page_items = self.layout.page_items();
+        let mut first = item + 1;
+        while first < page_items {
+            let count = SCAN_ITEMS.min(page_items - first);
+            let chunk = &mut buf[..count as usize * 2];
+            self.read_items(page, first, chunk);
+            for next in chunk.chunks_exact(2) {
+                if next == [0xffff, 0xffff] {
+                    return false;
+                }
+                if next[0] == tag {
+                    return true;
+                }
+            }
+            first += count;
+        }
+        false
+    }
+
+    fn rescue_if_full(&mut self, src_page: u32) -> EEPROMResult<u32> {
+        if !self.is_page_full(src_page) {
+            return Ok(src_page);
+        }
+
+        // Last word was not 0xffffffff, we need to rescue to the next page
+        self.compact_page(src_page)
+    }
+
+    fn compact_page(&mut self, src_page: u32) -> EEPROMResult<u32> {
+        let mut failures = 0;
+        loop {
+            // No-op if target page was prepared in advance
+            let tgt_page = self.erase_target(src_page)?;
+            match self.compact_to(src_page, tgt_page) {
+                // Target page which keeps failing to program is retired and the next one is used
+                Err(Error::Verify) if failures < PROGRAM_RETRIES => failures += 1,
+                Err(Error::Verify) if self.retire_page(tgt_page)? => failures = 0,
+                result => return result.map(|()| tgt_page),
+            }
+        }
+    }
+
+    fn compact_to(&mut self, src_page: u32, tgt_page: u32) -> EEPROMResult<()> {
+        // Values of the incomplete transaction are discarded
+        let end = self
+            .open_transaction(src_page)
+            .unwrap_or(self.layout.page_items());
+        self.copy_items(src_page, HEADER_ITEMS..end, tgt_page)?;
+
+        let generation = self.page_generation(src_page).wrapping_add(1);
+        self.activate_page(tgt_page, generation)?; // Mark target page as active
+        self.write_header(src_page, HEADER_STATUS, OBSOLETE_PAGE_MARKER)?; // Mark source as obsolete
+        Ok(())
+    }
+
+    // Migrate page written by eeprom 0.3 to the current format. Values are copied to the next
+    // page, which is marked as active afterwards; legacy page is erased by `init` later. If
+    // interrupted, either the legacy page is still the only active page (and migration starts
+    // over), or the target page is active and takes precedence over the legacy one.
+    fn migrate_legacy(&mut self, src_page: u32) -> EEPROMResult<u32> {
+        let tgt_page = self.erase_target(src_page)?;
+        // Legacy page header is a single item
+        self.copy_items(src_page, 1..self.layout.page_items(), tgt_page)?;
+        self.activate_page(tgt_page, 0)?;
+        Ok(tgt_page)
+    }
+
+    // Copy latest values from the given items of the source page to the erased target page,
+    // skipping the header of the target page
+    fn copy_items(&mut self, src_page: u32, items: Range<u32>, tgt_page: u32) -> EEPROMResult<()> {
+        let mut tgt_pos = HEADER_ITEMS;
+
+        // Start scanning source page from the end (to get the latest value)
+        for item in items.rev() {
+            let (tag, data) = self.read_item_tuple(src_page, item);
+            if !is_value_tag(tag) {
+                continue; // empty value or transaction record -- skip
+            }
+
+            if self.search(tgt_page, tgt_pos, tag).is_none() {
+                let high = self.counter_high(src_page, item, tag);
+                if tgt_pos + copy_slots(high) > self.layout.page_items() {
+                    // Only happens for pages written by eeprom 0.3, which have a shorter header
+                    return Err(Error::CapacityExceeded);
+                }
+                tgt_pos = self.copy_value(tgt_page, tgt_pos, tag, data, high)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Write the latest value of the tag to the target page of the compaction, followed by the
+    // counter record if the value belongs to a counter. Returns the position after the copy.
+    fn copy_value(
+        &mut self,
+        page: u32,
+        pos: u32,
+        tag: HalfWord,
+        data: HalfWord,
+        high: Option<HalfWord>,
+    ) -> EEPROMResult<u32> {
+        self.program_item(page, pos, tag, data)?;
+        if let Some(high) = high {
+            self.program_item(page, pos + 1, COUNTER_TAG, high)?;
+        }
+        Ok(pos + copy_slots(high))
+    }
+
+    // Next page after the given one which is not retired
+    fn next_page(&mut self, page: u32) -> u32 {
+        let count = self.layout.params().page_count;
+        (1..count)
+            .map(|i| (page + i) % count)
+            .find(|&next| !self.is_bad_page(next))
+            .unwrap_or((page + 1) % count)
+    }
+
+    fn first_usable_page(&mut self) -> u32 {
+        self.next_page(self.layout.params().page_count - 1)
+    }
+
+    // Erase the page next to the given one, so it could be used as a target of the compaction.
+    // Page which keeps failing to erase is retired and the next one is used instead.
+    fn erase_target(&mut self, src_page: u32) -> EEPROMResult<u32> {
+        let mut page = self.next_page(src_page);
+        loop {
+            match self.erase_page(page) {
+                Err(Error::Verify) if self.retire_page(page)? => page = self.next_page(page),
+                result => return result.map(|()| page),
+            }
+        }
+    }
+
+    // Retired page has format magic with zero status and version. Compacted pages keep the format
+    // version, and zeroed pages have no magic, so neither is taken for a retired one.
+    fn is_bad_page(&mut self, page: u32) -> bool {
+        self.page_status(page) == OBSOLETE_PAGE_MARKER
+            && self.read_header(page, HEADER_VERSION) == BAD_PAGE_VERSION
+            && self.read_header(page, HEADER_MAGIC) == FORMAT_MAGIC
+    }
+
+    // Check if there are more than two usable pages (they are required for compaction), so one
+    // could be retired
+    fn can_retire(&mut self) -> bool {
+        let mut usable = 0;
+        for page in 0..self.layout.params().page_count {
+            if !self.is_bad_page(page) {
+                usable += 1;
+            }
+        }
+        usable > 2
+    }
+
+    // Mark the page as bad, so it is skipped by compaction afterwards. Returns `false` if only two
+    // usable pages are left or the page cannot be marked.
+    fn retire_page(&mut self, page: u32) -> EEPROMResult<bool> {
+        if !self.can_retire() {
+            return Ok(false);
+        }
+
+        // Page is not blank, so half-words are programmed as is, without verification. Flash
+        // might only allow programming zero over a programmed half-word, so magic is written
+        // only if its half-word is erased.
         let page_offset = self.page_offset(page);
-        self.flash.read(&self.params, page_offset).unwrap()
+        match self.read_header(page, HEADER_MAGIC) {
+            FORMAT_MAGIC => (),
+            0xffff => self.flash.write(
+                &self.layout.params(),
+                page_offset + HEADER_MAGIC * 2,
+                FORMAT_MAGIC,
+            )?,
+            _ => return Ok(false),
+        }
+        self.flash.write(
+            &self.layout.params(),
+            page_offset + HEADER_VERSION * 2,
+            BAD_PAGE_VERSION,
+        )?;
+        self.flash.write(
+            &self.layout.params(),
+            page_offset + HEADER_STATUS * 2,
+            OBSOLETE_PAGE_MARKER,
+        )?;
+        if !self.is_bad_page(page) {
+            return Ok(false);
+        }
+        self.diagnostics.retired_pages += 1;
+        Ok(true)
+    }
+
+    fn search(&mut self, page: u32, max_item: u32, tag: HalfWord) -> Option<HalfWord> {
+        self.find_item(page, max_item, tag).map(|(_, data)| data)
+    }
+
+    // Find the latest item for the `tag`, returning its index and value
+    fn find_item(&mut self, page: u32, max_item: u32, tag: HalfWord) -> Option<(u32, HalfWord)> {
+        let mut buf = [0; SCAN_ITEMS as usize * 2];
+        let mut end = max_item;
+        while end > HEADER_ITEMS {
+            let first = end.saturating_sub(SCAN_ITEMS).max(HEADER_ITEMS);
+            let chunk = &mut buf[..(end - first) as usize * 2];
+            self.read_items(page, first, chunk);
+            for (i, item) in chunk.chunks_exact(2).enumerate().rev() {
+                if item[0] == tag {
+                    return Some((first + i as u32, item[1]));
+                }
+            }
+            end = first;
+        }
+        None
+    }
+
+    // Find the active page, resolving the case of multiple pages being marked as active. This
+    // happens when `rescue_if_full` is interrupted after marking the target page as active, but
+    // before erasing the source page. Target page always has the higher generation, so we prefer
+    // it over the source page. Fails if any of the active pages is written in a different format.
+    fn resolve_active(&mut self) -> EEPROMResult<Option<u32>> {
+        let mut active: Option<(u32, u32)> = None;
+        for page in 0..self.layout.params().page_count {
+            if self.page_status(page) != ACTIVE_PAGE_MARKER || self.is_legacy_page(page) {
+                continue;
+            }
+            self.check_header(page)?;
+
+            let generation = self.page_generation(page);
+            match active {
+                Some((_, current)) if current >= generation => (),
+                _ => active = Some((page, generation)),
+            }
+        }
+        Ok(active.map(|(page, _)| page))
+    }
+
+    // Find the active page written in the current format with current parameters, ignoring
+    // pages that are not compatible
+    fn find_valid_active(&mut self) -> Option<u32> {
+        self.pages().find_valid_active().unwrap()
+    }
+
+    // Find an active page written by eeprom 0.3 (see `Pages::find_legacy`)
+    fn find_legacy(&mut self) -> Option<u32> {
+        self.pages().find_legacy().unwrap()
+    }
+
+    fn is_legacy_page(&mut self, page: u32) -> bool {
+        self.pages().is_legacy_page(page).unwrap()
+    }
+
+    fn check_header(&mut self, page: u32) -> EEPROMResult<()> {
+        self.pages().check_header(page)
+    }
+
+    fn find_active(&mut self) -> Option<u32> {
+        (0..self.layout.params().page_count)
+            .find(|&page| self.page_status(page) == ACTIVE_PAGE_MARKER)
+    }
+
+    fn page_status(&mut self, page: u32) -> HalfWord {
+        self.read_header(page, HEADER_STATUS)
+    }
+
+    fn page_generation(&mut self, page: u32) -> u32 {
+        self.pages().page_generation(page).unwrap()
     }
 
-    fn set_page_status(&mut self, page: u32, status: HalfWord) -> FlashResult<()> {
+    fn read_header(&mut self, page: u32, field: u32) -> HalfWord {
+        self.pages().read_header(page, field).unwrap()
+    }
+
+    fn pages(&mut self) -> Pages<'_, F> {
+        Pages {
+            flash: &mut self.flash,
+            params: self.layout.params(),
+            page_items: self.layout.page_items(),
+        }
+    }
+
+    fn write_header(&mut self, page: u32, field: u32, value: HalfWord) -> EEPROMResult<()> {
         let page_offset = self.page_offset(page);
-        self.flash.write(&self.params, page_offset, status)
+        self.program_half_word(page_offset + field * 2, value)
+    }
+
+    // Write page header, marking the page as active. Status is written last, so interrupted
+    // activation leaves the page in a non-active state.
+    fn activate_page(&mut self, page: u32, generation: u32) -> EEPROMResult<()> {
+        let fingerprint = self.layout.params().fingerprint();
+        self.write_header(page, HEADER_VERSION, FORMAT_VERSION)?;
+        self.write_header(page, HEADER_MAGIC, FORMAT_MAGIC)?;
+        self.write_header(page, HEADER_FINGERPRINT, fingerprint)?;
+        self.write_header(page, HEADER_GENERATION, generation as HalfWord)?;
+        self.write_header(page, HEADER_GENERATION + 1, (generation >> 16) as HalfWord)?;
+        self.write_header(page, HEADER_STATUS, ACTIVE_PAGE_MARKER)
     }
 
     fn page_offset(&self, page: u32) -> u32 {
@@ -301,21 +1882,28 @@ where
 
     fn item_offset(&self, page: u32, item: u32) -> u32 {
         debug_assert!(
-            item < self.page_items,
+            item < self.layout.page_items(),
             "item must be less than the amount of items per page"
         );
         debug_assert!(
-            page < self.params.page_count,
+            page < self.layout.params().page_count,
             "page must be less than the amount of pages"
         );
-        ((self.params.first_page + page) * self.page_items + item) * ITEM_SIZE
+        ((self.layout.params().first_page + page) * self.layout.page_items() + item) * ITEM_SIZE
     }
 
     fn read_item(&mut self, page: u32, item: u32) -> Word {
-        let offset = self.item_offset(page, item);
-        let tag = self.flash.read(&self.params, offset).unwrap();
-        let data = self.flash.read(&self.params, offset + 2).unwrap();
-        (u32::from(data) << 16) + u32::from(tag)
+        let mut buf = [0; 2];
+        self.read_items(page, item, &mut buf);
+        (u32::from(buf[1]) << 16) + u32::from(buf[0])
+    }
+
+    // Read consecutive items starting at `first` into `buf` (tag and data of every item)
+    fn read_items(&mut self, page: u32, first: u32, buf: &mut [HalfWord]) {
+        let offset = self.item_offset(page, first);
+        self.flash
+            .read_slice(&self.layout.params(), offset, buf)
+            .unwrap();
     }
 
     fn read_item_tuple(&mut self, page: u32, item: u32) -> (HalfWord, HalfWord) {
@@ -323,23 +1911,75 @@ where
         ((item & 0xffff) as HalfWord, (item >> 16) as HalfWord)
     }
 
-    fn erase_page(&mut self, page: u32) -> FlashResult<()> {
+    fn erase_page(&mut self, page: u32) -> EEPROMResult<()> {
         if self.is_page_dirty(page) {
-            let page_offset = self.page_offset(page);
-            let result = self.flash.page_erase(&self.params, page_offset);
-            debug_assert!(!self.is_page_dirty(page));
-            result
+            // Erasing the target page invalidates incremental compaction
+            if matches!(self.compaction, Some(state) if state.target == page) {
+                self.compaction = None;
+            }
+
+            self.erase_verified(page)
         } else {
             Ok(())
         }
     }
 
+    // Erase the page, checking that it is blank afterwards in the verify mode
+    fn erase_verified(&mut self, page: u32) -> EEPROMResult<()> {
+        let page_offset = self.page_offset(page);
+        let mut retries = 0;
+        loop {
+            self.flash.page_erase(&self.layout.params(), page_offset)?;
+            if !self.verify {
+                debug_assert!(!self.is_page_dirty(page));
+                return Ok(());
+            }
+            if !self.is_page_dirty(page) {
+                return Ok(());
+            }
+            if retries == ERASE_RETRIES {
+                return Err(Error::Verify);
+            }
+            retries += 1;
+            self.diagnostics.erase_retries += 1;
+        }
+    }
+
+    fn is_page_full(&mut self, page: u32) -> bool {
+        self.pages().is_page_full(page).unwrap()
+    }
+
+    // Index of the item following the last written one
+    fn first_free_item(&mut self, page: u32) -> u32 {
+        let mut buf = [0; SCAN_ITEMS as usize * 2];
+        let mut end = self.layout.page_items();
+        while end > HEADER_ITEMS {
+            let first = end.saturating_sub(SCAN_ITEMS).max(HEADER_ITEMS);
+            let chunk = &mut buf[..(end - first) as usize * 2];
+            self.read_items(page, first, chunk);
+            if let Some(i) = chunk
+                .chunks_exact(2)
+                .rposition(|item| item != [0xffff, 0xffff])
+            {
+                return first + i as u32 + 1;
+            }
+            end = first;
+        }
+        HEADER_ITEMS
+    }
+
     fn is_page_dirty(&mut self, page: u32) -> bool {
-        for item in 0..self.page_items {
-            let value = self.read_item(page, item);
-            if value != ERASED_ITEM {
+        let mut buf = [0; SCAN_ITEMS as usize * 2];
+        let page_items = self.layout.page_items();
+        let mut item = 0;
+        while item < page_items {
+            let count = SCAN_ITEMS.min(page_items - item);
+            let chunk = &mut buf[..count as usize * 2];
+            self.read_items(page, item, chunk);
+            if chunk.iter().any(|&value| value != 0xffff) {
                 return true;
             }
+            item += count;
         }
         false
     }
@@ -350,13 +1990,56 @@ where
         pos: u32,
         tag: HalfWord,
         data: HalfWord,
-    ) -> FlashResult<()> {
+    ) -> EEPROMResult<()> {
         let item_addr = self.item_offset(page, pos);
 
         // Not found -- write the value first, so if we fail for whatever reason,
         // we don't have the default value of `0xffff` for the item with `tag`.
-        self.flash.write(&self.params, item_addr + 2, data)?;
-        self.flash.write(&self.params, item_addr, tag)?;
+        self.program_data(item_addr, data)?;
+        self.program_tag(item_addr, tag)
+    }
+
+    // Write data of the item at the given address, marking the item invalid if it is read back
+    // with different data
+    fn program_data(&mut self, item_addr: u32, data: HalfWord) -> EEPROMResult<()> {
+        if let Err(err) = self.program_half_word(item_addr + 2, data) {
+            if err == Error::Verify {
+                // Tag is not written yet, so the item could be marked as invalid
+                self.flash
+                    .write(&self.layout.params(), item_addr, INVALID_TAG)?;
+                self.diagnostics.invalid_items += 1;
+            }
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    // Write tag of the item at the given address, marking the item invalid if it is read back
+    // with a different tag
+    fn program_tag(&mut self, item_addr: u32, tag: HalfWord) -> EEPROMResult<()> {
+        if let Err(err) = self.program_half_word(item_addr, tag) {
+            if err == Error::Verify {
+                // Item might hold another tag now, so it is marked as invalid. If it cannot be
+                // marked, writing is not retried, as the item would be taken for a valid one.
+                self.flash
+                    .write(&self.layout.params(), item_addr, INVALID_TAG)?;
+                self.diagnostics.invalid_items += 1;
+                if self.flash.read(&self.layout.params(), item_addr)? != INVALID_TAG {
+                    return Err(Error::Corrupted);
+                }
+            }
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    // Write half-word, reading it back in the verify mode
+    fn program_half_word(&mut self, offset: u32, value: HalfWord) -> EEPROMResult<()> {
+        self.flash.write(&self.layout.params(), offset, value)?;
+        if self.verify && self.flash.read(&self.layout.params(), offset)? != value {
+            self.diagnostics.program_failures += 1;
+            return Err(Error::Verify);
+        }
         Ok(())
     }
 }
@@ -0,0 +1,202 @@
+use crate::{EEPROMResult, Error, Flash, HalfWord, Layout, Params, EEPROM, HEADER_ITEMS};
+
+// Checksum of the value is stored under the tag with this bit set
+const CHECKSUM_TAG: HalfWord = 0b0100_0000_0000_0000;
+
+/// EEPROM controller, which stores every value in two independent regions (for example, in two
+/// different flash banks), together with its checksum. Corrupted or diverged copy is detected by
+/// `read` and `init` and repaired from the other one.
+///
+/// Value and its checksum are written to each region atomically (see `EEPROM::transaction`), to the
+/// primary region first, so if `write` is interrupted, the value is either rolled back to the old
+/// one (if primary copy is incomplete) or completed (if only the secondary copy is outdated). Every
+/// value takes two slots of each region, and writing it needs one more item, so at most
+/// `(EEPROM::capacity() - 1) / 2` values could be stored.
+///
+/// # Examples
+/// ```rust,no_run
+/// # use eeprom::{Flash, MirroredEEPROM, Params};
+/// # fn example<F: Flash>(flash: F, bank1: Params, bank2: Params) {
+/// let mut eeprom = MirroredEEPROM::new(bank1, bank2, flash);
+/// eeprom.init().expect("failed to init EEPROM");
+/// eeprom.write(1, 0xdead).unwrap();
+/// assert_eq!(Some(0xdead), eeprom.read(1).unwrap());
+/// # }
+/// ```
+pub struct MirroredEEPROM<F> {
+    eeprom: EEPROM<F>,
+    primary: Params,
+    secondary: Params,
+}
+
+// State of a copy of the value in one of the regions
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Replica {
+    Missing,
+    Valid(HalfWord),
+    Corrupted,
+}
+
+impl<F> MirroredEEPROM<F>
+where
+    F: Flash,
+{
+    /// Create new mirrored EEPROM controller.
+    ///
+    /// # Panics
+    /// * panics if regions overlap
+    pub fn new(primary: Params, secondary: Params, flash: F) -> Self {
+        assert!(
+            primary.first_page + primary.page_count <= secondary.first_page
+                || secondary.first_page + secondary.page_count <= primary.first_page,
+            "regions must not overlap"
+        );
+        MirroredEEPROM {
+            eeprom: EEPROM::new(primary, flash),
+            primary,
+            secondary,
+        }
+    }
+
+    /// Initialize both regions (see `EEPROM::init`) and repair values which are corrupted or
+    /// diverged in one of them. Region written in an unknown or incompatible format is erased and
+    /// rebuilt from the other one; error is returned only if both regions are unusable. Values
+    /// corrupted in both regions are left as is, so `read` returns `Error::Corrupted` for them.
+    pub fn init(&mut self) -> EEPROMResult<()> {
+        self.eeprom.configure(self.primary);
+        let primary = self.eeprom.init();
+        self.eeprom.configure(self.secondary);
+        let secondary = self.eeprom.init();
+        match (primary, secondary) {
+            (Ok(()), Ok(())) => (),
+            (Err(Error::Flash(err)), _) | (_, Err(Error::Flash(err))) => return Err(err.into()),
+            (Err(err), Err(_)) => return Err(err),
+            (Err(_), Ok(())) => {
+                self.eeprom.configure(self.primary);
+                self.eeprom.erase()?;
+            }
+            (Ok(()), Err(_)) => {
+                self.eeprom.configure(self.secondary);
+                self.eeprom.erase()?;
+            }
+        }
+
+        // Repair values of both regions; start over if repair moved the active page
+        'scan: loop {
+            for params in [self.primary, self.secondary] {
+                self.eeprom.configure(params);
+                let page = self.eeprom.find_active().expect("cannot find active page");
+                for item in HEADER_ITEMS..self.eeprom.layout.page_items() {
+                    self.eeprom.configure(params);
+                    let (tag, _) = self.eeprom.read_item_tuple(page, item);
+                    if tag & 0b1100_0000_0000_0000 != 0 {
+                        continue; // checksum or reserved tag -- skip
+                    }
+
+                    match self.read(tag) {
+                        Ok(_) | Err(Error::Corrupted) => (),
+                        Err(err) => return Err(err),
+                    }
+                    self.eeprom.configure(params);
+                    if self.eeprom.find_active() != Some(page) {
+                        continue 'scan;
+                    }
+                }
+            }
+            return Ok(());
+        }
+    }
+
+    /// Erase all values stored in both regions
+    pub fn erase(&mut self) -> EEPROMResult<()> {
+        for params in [self.primary, self.secondary] {
+            self.eeprom.configure(params);
+            self.eeprom.erase()?;
+        }
+        Ok(())
+    }
+
+    /// Read value for a specified tag, repairing the copy which is corrupted or diverged. Returns
+    /// `Error::Corrupted` if neither copy is valid, unless the value was never written to either
+    /// region.
+    ///
+    /// # Panics
+    /// * panics if active page cannot be found
+    /// * panics if tag value has one of the two most significant bits set to `1` (reserved
+    ///   values)
+    pub fn read(&mut self, tag: HalfWord) -> EEPROMResult<Option<HalfWord>> {
+        assert_eq!(tag & 0b1100_0000_0000_0000, 0, "two msb bits are reserved");
+
+        let primary = self.load(self.primary, tag);
+        let secondary = self.load(self.secondary, tag);
+        match (primary, secondary) {
+            (Replica::Valid(data), Replica::Valid(other)) if data == other => Ok(Some(data)),
+            // Primary copy is written first, so it is newer
+            (Replica::Valid(data), _) => {
+                self.store(self.secondary, tag, data)?;
+                Ok(Some(data))
+            }
+            (_, Replica::Valid(data)) => {
+                self.store(self.primary, tag, data)?;
+                Ok(Some(data))
+            }
+            (Replica::Missing, Replica::Missing) => Ok(None),
+            _ => Err(Error::Corrupted),
+        }
+    }
+
+    /// Write value for a specified tag to both regions.
+    ///
+    /// # Panics
+    /// * panics if active page cannot be found
+    /// * panics if tag value has one of the two most significant bits set to `1` (reserved
+    ///   values)
+    /// * see `EEPROM::write`
+    pub fn write(&mut self, tag: HalfWord, data: HalfWord) -> EEPROMResult<()> {
+        assert_eq!(tag & 0b1100_0000_0000_0000, 0, "two msb bits are reserved");
+
+        self.store(self.primary, tag, data)?;
+        self.store(self.secondary, tag, data)
+    }
+
+    /// Return the underlying EEPROM controller, configured for the primary region.
+    pub fn into_inner(mut self) -> EEPROM<F> {
+        self.eeprom.configure(self.primary);
+        self.eeprom
+    }
+
+    fn load(&mut self, params: Params, tag: HalfWord) -> Replica {
+        self.eeprom.configure(params);
+        let data = self.eeprom.read(tag);
+        let sum = self.eeprom.read(tag | CHECKSUM_TAG);
+        match (data, sum) {
+            (None, None) => Replica::Missing,
+            (Some(data), Some(sum)) if sum == checksum(tag, data) => Replica::Valid(data),
+            _ => Replica::Corrupted,
+        }
+    }
+
+    fn store(&mut self, params: Params, tag: HalfWord, data: HalfWord) -> EEPROMResult<()> {
+        self.eeprom.configure(params);
+        self.eeprom.transaction(|tx| {
+            tx.write(tag, data);
+            tx.write(tag | CHECKSUM_TAG, checksum(tag, data));
+        })
+    }
+}
+
+// CRC-16/CCITT of the tag and the value
+fn checksum(tag: HalfWord, data: HalfWord) -> HalfWord {
+    let mut crc: HalfWord = 0xffff;
+    for byte in tag.to_le_bytes().into_iter().chain(data.to_le_bytes()) {
+        crc ^= HalfWord::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
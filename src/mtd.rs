@@ -0,0 +1,273 @@
+use crate::{EEPROMExt, Flash, FlashResult, HalfWord, Params, EEPROM};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::mem::size_of;
+use std::os::unix::fs::{FileExt, FileTypeExt};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::vec;
+
+// Encoding of the ioctl request numbers (`_IOC` from `<asm/ioctl.h>`), which differs between
+// architectures
+#[cfg(not(any(
+    target_arch = "mips",
+    target_arch = "mips64",
+    target_arch = "powerpc",
+    target_arch = "powerpc64",
+    target_arch = "sparc",
+    target_arch = "sparc64"
+)))]
+mod ioc {
+    pub const SIZE_BITS: u32 = 14;
+    pub const WRITE: u32 = 1;
+    pub const READ: u32 = 2;
+}
+
+#[cfg(any(
+    target_arch = "mips",
+    target_arch = "mips64",
+    target_arch = "powerpc",
+    target_arch = "powerpc64",
+    target_arch = "sparc",
+    target_arch = "sparc64"
+))]
+mod ioc {
+    pub const SIZE_BITS: u32 = 13;
+    pub const WRITE: u32 = 4;
+    pub const READ: u32 = 2;
+}
+
+const fn ioc(dir: u32, kind: u8, nr: u8, size: usize) -> u32 {
+    (dir << (16 + ioc::SIZE_BITS)) | ((size as u32) << 16) | ((kind as u32) << 8) | nr as u32
+}
+
+// `_IOR('M', 1, struct mtd_info_user)`
+pub(crate) const MEMGETINFO: u32 = ioc(ioc::READ, b'M', 1, size_of::<MtdInfoUser>());
+// `_IOW('M', 2, struct erase_info_user)`
+pub(crate) const MEMERASE: u32 = ioc(ioc::WRITE, b'M', 2, size_of::<EraseInfoUser>());
+
+// `MTD_NORFLASH` device type
+const MTD_NORFLASH: u8 = 3;
+
+const ERASED_BYTE: u8 = 0xff;
+
+// Mirrors `struct mtd_info_user` from `<mtd/mtd-abi.h>`
+#[repr(C)]
+#[derive(Default)]
+pub(crate) struct MtdInfoUser {
+    pub(crate) kind: u8,
+    pub(crate) flags: u32,
+    pub(crate) size: u32,
+    pub(crate) erasesize: u32,
+    pub(crate) writesize: u32,
+    pub(crate) oobsize: u32,
+    pub(crate) padding: u64,
+}
+
+impl MtdInfoUser {
+    // Geometry of the partition, if it could hold the EEPROM: NOR flash allows programming
+    // half-words in place, while NAND requires writing whole pages
+    pub(crate) fn geometry(&self) -> io::Result<MtdInfo> {
+        if self.kind != MTD_NORFLASH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "MTD device is not a NOR flash",
+            ));
+        }
+        if self.writesize > 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "MTD device does not allow programming half-words",
+            ));
+        }
+        Ok(MtdInfo {
+            size: self.size,
+            erase_size: self.erasesize,
+            write_size: self.writesize,
+        })
+    }
+}
+
+// Mirrors `struct erase_info_user` from `<mtd/mtd-abi.h>`
+#[repr(C)]
+struct EraseInfoUser {
+    start: u32,
+    length: u32,
+}
+
+/// Geometry of the MTD partition.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MtdInfo {
+    /// Total size of the partition, in bytes
+    pub size: u32,
+    /// Size of the erase block, in bytes
+    pub erase_size: u32,
+    /// Minimal writable unit, in bytes
+    pub write_size: u32,
+}
+
+/// `Flash` implementation on top of the Linux MTD character device (`/dev/mtdN`).
+///
+/// Offsets passed by the EEPROM controller are used as offsets in the partition, so
+/// `Params::first_page` is counted from the beginning of the partition and EEPROM page size
+/// must match the erase block size reported in `MtdInfo`.
+pub struct MtdFlash {
+    file: File,
+    info: MtdInfo,
+    emulated: bool,
+}
+
+impl MtdFlash {
+    /// Open MTD character device at `path` and query its geometry via `MEMGETINFO`. Devices
+    /// other than NOR flash, or which cannot program half-words, are rejected.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<MtdFlash> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        if !file.metadata()?.file_type().is_char_device() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not an MTD character device",
+            ));
+        }
+
+        let mut raw = MtdInfoUser::default();
+        // Safety: `MEMGETINFO` fills in `struct mtd_info_user`, which `raw` mirrors.
+        let result = unsafe { libc::ioctl(file.as_raw_fd(), MEMGETINFO as _, &mut raw) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(MtdFlash {
+            file,
+            info: raw.geometry()?,
+            emulated: false,
+        })
+    }
+
+    /// Open regular file at `path` as a stand-in for the MTD partition with the given erase
+    /// block size. Erase fills the block with `0xff` and programming can only clear bits, same
+    /// as the NOR flash behind the MTD device. File is created (erased) if it does not exist.
+    pub fn open_emulated<P: AsRef<Path>>(
+        path: P,
+        size: u32,
+        erase_size: u32,
+    ) -> io::Result<MtdFlash> {
+        if erase_size == 0 || !size.is_multiple_of(erase_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "size must be a multiple of the erase block size",
+            ));
+        }
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let len = file.metadata()?.len();
+        if len < u64::from(size) {
+            file.write_all_at(&vec![ERASED_BYTE; (u64::from(size) - len) as usize], len)?;
+        }
+
+        let info = MtdInfo {
+            size,
+            erase_size,
+            write_size: 1,
+        };
+        Ok(MtdFlash {
+            file,
+            info,
+            emulated: true,
+        })
+    }
+
+    /// Geometry of the underlying partition.
+    pub fn info(&self) -> &MtdInfo {
+        &self.info
+    }
+
+    fn check_range(&self, offset: u32, len: u32) -> io::Result<()> {
+        if offset
+            .checked_add(len)
+            .is_none_or(|end| end > self.info.size)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "offset is outside of the MTD partition",
+            ));
+        }
+        Ok(())
+    }
+
+    fn read_half_word(&mut self, offset: u32) -> io::Result<HalfWord> {
+        self.check_range(offset, 2)?;
+        let mut buf = [0u8; 2];
+        self.file.read_exact_at(&mut buf, u64::from(offset))?;
+        Ok(HalfWord::from_le_bytes(buf))
+    }
+
+    fn write_half_word(&mut self, offset: u32, data: HalfWord) -> io::Result<()> {
+        self.check_range(offset, 2)?;
+        let data = if self.emulated {
+            // NOR flash can only clear bits when programming
+            self.read_half_word(offset)? & data
+        } else {
+            data
+        };
+        self.file
+            .write_all_at(&data.to_le_bytes(), u64::from(offset))
+    }
+
+    fn erase_block(&mut self, address: u32) -> io::Result<()> {
+        self.check_range(address, self.info.erase_size)?;
+        if !address.is_multiple_of(self.info.erase_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "address is not aligned to the erase block",
+            ));
+        }
+
+        if self.emulated {
+            let block = vec![ERASED_BYTE; self.info.erase_size as usize];
+            return self.file.write_all_at(&block, u64::from(address));
+        }
+
+        let erase = EraseInfoUser {
+            start: address,
+            length: self.info.erase_size,
+        };
+        // Safety: `MEMERASE` reads `struct erase_info_user`, which `erase` mirrors.
+        let result = unsafe { libc::ioctl(self.file.as_raw_fd(), MEMERASE as _, &erase) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl EEPROMExt for &mut MtdFlash {
+    fn eeprom(self, params: Params) -> EEPROM<Self> {
+        EEPROM::new(params, self)
+    }
+}
+
+impl Flash for &mut MtdFlash {
+    fn read(&mut self, _params: &Params, offset: u32) -> FlashResult<HalfWord> {
+        self.read_half_word(offset).map_err(|_| ())
+    }
+
+    fn write(&mut self, _params: &Params, offset: u32, data: HalfWord) -> FlashResult<()> {
+        self.write_half_word(offset, data).map_err(|_| ())
+    }
+
+    fn page_erase(&mut self, params: &Params, address: u32) -> FlashResult<()> {
+        if params.page_size != self.info.erase_size {
+            return Err(());
+        }
+        self.erase_block(address).map_err(|_| ())
+    }
+
+    fn clears_bits(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{
+    is_value_tag, EEPROMResult, Error, FlashResult, HalfWord, Params, ReadFlash,
+    ACTIVE_PAGE_MARKER, FORMAT_MAGIC, FORMAT_VERSION, HEADER_FINGERPRINT, HEADER_GENERATION,
+    HEADER_ITEMS, HEADER_MAGIC, HEADER_STATUS, HEADER_VERSION, ITEM_SIZE, TRANSACTION_TAG,
+};
+
+// Read-only access to the pages of the EEPROM region: parsing of the page header and lookup of the
+// active page. Shared by `EEPROM` and `EEPROMReader`, so both agree on which values are visible.
+pub(crate) struct Pages<'a, F> {
+    pub(crate) flash: &'a mut F,
+    pub(crate) params: Params,
+    pub(crate) page_items: u32,
+}
+
+impl<F> Pages<'_, F>
+where
+    F: ReadFlash,
+{
+    // Find the active page written in the current format with current parameters, ignoring
+    // pages that are not compatible. Page with the highest generation is preferred, as it is the
+    // target of the interrupted compaction.
+    pub(crate) fn find_valid_active(&mut self) -> FlashResult<Option<u32>> {
+        let mut active: Option<(u32, u32)> = None;
+        for page in 0..self.params.page_count {
+            if self.page_status(page)? != ACTIVE_PAGE_MARKER {
+                continue;
+            }
+            match self.check_header(page) {
+                Ok(()) => (),
+                Err(Error::Flash(err)) => return Err(err),
+                Err(_) => continue,
+            }
+
+            let generation = self.page_generation(page)?;
+            match active {
+                Some((_, current)) if current >= generation => (),
+                _ => active = Some((page, generation)),
+            }
+        }
+        Ok(active.map(|(page, _)| page))
+    }
+
+    // Find an active page written by eeprom 0.3. If there are multiple (interrupted compaction),
+    // prefer the one which is not full, as the source page of the compaction is always full.
+    pub(crate) fn find_legacy(&mut self) -> FlashResult<Option<u32>> {
+        let mut legacy = None;
+        for page in 0..self.params.page_count {
+            if !self.is_legacy_page(page)? {
+                continue;
+            }
+            match legacy {
+                Some(current) if !self.is_page_full(current)? || self.is_page_full(page)? => (),
+                _ => legacy = Some(page),
+            }
+        }
+        Ok(legacy)
+    }
+
+    // Page written by eeprom 0.3 has `0xffff` instead of the format version
+    pub(crate) fn is_legacy_page(&mut self, page: u32) -> FlashResult<bool> {
+        Ok(self.page_status(page)? == ACTIVE_PAGE_MARKER
+            && self.read_header(page, HEADER_VERSION)? == 0xffff)
+    }
+
+    pub(crate) fn check_header(&mut self, page: u32) -> EEPROMResult<()> {
+        let version = self.read_header(page, HEADER_VERSION)?;
+        if self.read_header(page, HEADER_MAGIC)? != FORMAT_MAGIC {
+            Err(Error::UnknownFormat)
+        } else if version != FORMAT_VERSION {
+            Err(Error::UnsupportedVersion(version))
+        } else if self.read_header(page, HEADER_FINGERPRINT)? != self.params.fingerprint() {
+            Err(Error::ParamsMismatch)
+        } else {
+            Ok(())
+        }
+    }
+
+    // Find the record of the last transaction written to the page, if not all of its values are
+    // written (value which failed to verify is marked invalid, so it is not written either)
+    pub(crate) fn open_transaction(&mut self, page: u32) -> FlashResult<Option<u32>> {
+        for item in (HEADER_ITEMS..self.page_items).rev() {
+            let (tag, count) = self.read_item(page, item)?;
+            if tag == TRANSACTION_TAG {
+                let end = item + 1 + u32::from(count);
+                if end > self.page_items {
+                    return Ok(Some(item));
+                }
+                for value in item + 1..end {
+                    if !is_value_tag(self.read_item(page, value)?.0) {
+                        return Ok(Some(item));
+                    }
+                }
+                return Ok(None);
+            }
+        }
+        Ok(None)
+    }
+
+    pub(crate) fn is_page_full(&mut self, page: u32) -> FlashResult<bool> {
+        // Check if last word of the page was written or not
+        // Note that we check both data and the tag as in case of failure we might write
+        // data, but not the tag.
+        Ok(self.read_item(page, self.page_items - 1)? != (0xffff, 0xffff))
+    }
+
+    pub(crate) fn page_status(&mut self, page: u32) -> FlashResult<HalfWord> {
+        self.read_header(page, HEADER_STATUS)
+    }
+
+    pub(crate) fn page_generation(&mut self, page: u32) -> FlashResult<u32> {
+        let low = self.read_header(page, HEADER_GENERATION)?;
+        let high = self.read_header(page, HEADER_GENERATION + 1)?;
+        Ok((u32::from(high) << 16) + u32::from(low))
+    }
+
+    pub(crate) fn read_header(&mut self, page: u32, field: u32) -> FlashResult<HalfWord> {
+        let offset = self.item_offset(page, 0) + field * 2;
+        self.flash.read(&self.params, offset)
+    }
+
+    pub(crate) fn read_item(&mut self, page: u32, item: u32) -> FlashResult<(HalfWord, HalfWord)> {
+        let offset = self.item_offset(page, item);
+        let tag = self.flash.read(&self.params, offset)?;
+        let data = self.flash.read(&self.params, offset + 2)?;
+        Ok((tag, data))
+    }
+
+    fn item_offset(&self, page: u32, item: u32) -> u32 {
+        debug_assert!(
+            item < self.page_items,
+            "item must be less than the amount of items per page"
+        );
+        debug_assert!(
+            page < self.params.page_count,
+            "page must be less than the amount of pages"
+        );
+        ((self.params.first_page + page) * self.page_items + item) * ITEM_SIZE
+    }
+}
@@ -0,0 +1,135 @@
+use crate::pages::Pages;
+use crate::{EEPROMResult, FlashResult, HalfWord, Layout, Params, ReadFlash, HEADER_ITEMS};
+
+/// Read-only EEPROM access, for example, for a bootloader which needs a few settings but must
+/// never erase or compact pages. Flash is never modified, and any on-flash state is tolerated:
+/// values are read from the page `init` would keep as active, ignoring values of an incomplete
+/// transaction (which `init` discards). If there is no such page, no values are found.
+///
+/// # Examples
+/// ```rust,no_run
+/// # use eeprom::{EEPROMReader, MappedFlash, Params};
+/// # fn example(params: Params) {
+/// // STM32 maps flash memory at 0x0800_0000
+/// let flash = unsafe { MappedFlash::new(0x0800_0000 as *const u8) };
+/// let mut eeprom = EEPROMReader::new(params, flash);
+/// let stay_in_dfu = eeprom.read(1) == Ok(Some(1));
+/// # }
+/// ```
+pub struct EEPROMReader<F, L = Params> {
+    layout: L,
+    flash: F,
+    // Page the values are read from, with the range of its items holding values (found by the
+    // first `read`)
+    active: Option<Option<(u32, u32, u32)>>,
+}
+
+impl<F> EEPROMReader<F>
+where
+    F: ReadFlash,
+{
+    /// Create read-only EEPROM access with given configuration.
+    pub fn new(params: Params, flash: F) -> Self {
+        EEPROMReader::with_layout(params, flash)
+    }
+}
+
+impl<F, L> EEPROMReader<F, L>
+where
+    F: ReadFlash,
+    L: Layout,
+{
+    /// Create read-only EEPROM access with the given layout.
+    pub fn with_layout(layout: L, flash: F) -> Self {
+        EEPROMReader {
+            layout,
+            flash,
+            active: None,
+        }
+    }
+
+    /// Read value for a specified tag. Flash is scanned for the active page on the first call.
+    ///
+    /// # Panics
+    /// * panics if tag value has the most significant bit set to `1` (reserved value)
+    pub fn read(&mut self, tag: HalfWord) -> EEPROMResult<Option<HalfWord>> {
+        assert_eq!(tag & 0b1000_0000_0000_0000, 0, "msb bit of `1` is reserved");
+
+        let active = match self.active {
+            Some(active) => active,
+            None => {
+                let active = self.find_active()?;
+                self.active = Some(active);
+                active
+            }
+        };
+        let (page, first, end) = match active {
+            Some(active) => active,
+            None => return Ok(None),
+        };
+        let mut pages = self.pages();
+        for item in (first..end).rev() {
+            let (t, data) = pages.read_item(page, item)?;
+            if t == tag {
+                return Ok(Some(data));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Return the underlying flash.
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    // Find the page `init` would keep as active: active page in the current format with the
+    // highest generation, or active page written by eeprom 0.3
+    fn find_active(&mut self) -> FlashResult<Option<(u32, u32, u32)>> {
+        let mut pages = self.pages();
+        if let Some(page) = pages.find_valid_active()? {
+            // Values of the incomplete transaction are discarded
+            let end = pages.open_transaction(page)?.unwrap_or(pages.page_items);
+            return Ok(Some((page, HEADER_ITEMS, end)));
+        }
+        // Legacy page header is a single item
+        let legacy = pages.find_legacy()?;
+        Ok(legacy.map(|page| (page, 1, pages.page_items)))
+    }
+
+    fn pages(&mut self) -> Pages<'_, F> {
+        Pages {
+            flash: &mut self.flash,
+            params: self.layout.params(),
+            page_items: self.layout.page_items(),
+        }
+    }
+}
+
+/// `ReadFlash` implementation reading memory-mapped flash directly, without going through the
+/// flash controller.
+pub struct MappedFlash {
+    base: *const u8,
+}
+
+impl MappedFlash {
+    /// Create flash access reading at `base` plus the offset.
+    ///
+    /// # Safety
+    /// `base` must be the address flash memory is mapped at (for example, `0x0800_0000` on
+    /// STM32), and the whole EEPROM region must be readable and aligned to two bytes.
+    pub unsafe fn new(base: *const u8) -> Self {
+        MappedFlash { base }
+    }
+}
+
+impl ReadFlash for MappedFlash {
+    fn read(&mut self, _params: &Params, offset: u32) -> FlashResult<HalfWord> {
+        // Safety: region is readable, as promised by the caller of `new`
+        Ok(unsafe {
+            self.base
+                .add(offset as usize)
+                .cast::<HalfWord>()
+                .read_volatile()
+        })
+    }
+}
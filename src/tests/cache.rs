@@ -0,0 +1,80 @@
+use super::{assert_flash, layout, MockFlash};
+use crate::{CachedEEPROM, EEPROMExt, Error};
+
+#[test]
+fn test_cache_coalesce() {
+    let mut mcu = MockFlash::load("src/tests/test-data/empty.txt", 1024, 2);
+    {
+        let mut cache: CachedEEPROM<_, _, 4> = CachedEEPROM::new(mcu.eeprom(layout(0, 2)));
+        for value in 0..100 {
+            cache.write(1, value).unwrap();
+        }
+        cache.write(2, 0xbeef).unwrap();
+        cache.write(1, 0xdead).unwrap();
+        assert!(cache.is_dirty());
+        assert_eq!(0xdead, cache.read(1).unwrap());
+        assert!(cache.eeprom().read(1).is_none());
+
+        cache.flush().unwrap();
+        assert!(!cache.is_dirty());
+        assert_eq!(0xdead, cache.read(1).unwrap());
+    }
+    assert_flash(&mcu, "src/tests/test-data/valid-simple.txt");
+}
+
+#[test]
+fn test_cache_flush_unchanged() {
+    let mut mcu = MockFlash::load("src/tests/test-data/valid-simple.txt", 1024, 2);
+    {
+        let mut cache: CachedEEPROM<_, _, 4> = CachedEEPROM::new(mcu.eeprom(layout(0, 2)));
+        cache.write(1, 0x1234).unwrap();
+        cache.write(1, 0xdead).unwrap();
+        cache.flush().unwrap();
+    }
+    assert_flash(&mcu, "src/tests/test-data/valid-simple.txt");
+}
+
+#[test]
+fn test_cache_flush_when_full() {
+    let mut mcu = MockFlash::load("src/tests/test-data/empty.txt", 1024, 2);
+    let mut cache: CachedEEPROM<_, _, 1> = CachedEEPROM::new(mcu.eeprom(layout(0, 2)));
+    cache.write(1, 0xdead).unwrap();
+    cache.write(2, 0xbeef).unwrap();
+    assert_eq!(0xdead, cache.eeprom().read(1).unwrap());
+    assert!(cache.eeprom().read(2).is_none());
+    assert_eq!(0xbeef, cache.read(2).unwrap());
+}
+
+#[test]
+fn test_cache_flush_threshold() {
+    let mut mcu = MockFlash::load("src/tests/test-data/empty.txt", 1024, 2);
+    let mut cache: CachedEEPROM<_, _, 4> = CachedEEPROM::new(mcu.eeprom(layout(0, 2)));
+    cache.set_flush_threshold(3);
+    cache.write(1, 1).unwrap();
+    cache.write(1, 2).unwrap();
+    assert!(cache.is_dirty());
+    cache.write(1, 3).unwrap();
+    assert!(!cache.is_dirty());
+    assert_eq!(3, cache.into_inner().read(1).unwrap());
+}
+
+#[test]
+fn test_cache_try_flush() {
+    let mut mcu = MockFlash::load("src/tests/test-data/full-simple.txt", 1024, 2);
+    {
+        let mut cache: CachedEEPROM<_, _, 4> = CachedEEPROM::new(mcu.eeprom(layout(0, 2)));
+        cache.write(1, 0x1234).unwrap();
+        cache.write(3, 0xacdb).unwrap();
+        assert_eq!(Err(Error::WouldCompact), cache.try_flush());
+        assert!(cache.is_dirty());
+        assert_eq!(0x1234, cache.read(1).unwrap());
+    }
+    assert_flash(&mcu, "src/tests/test-data/full-simple.txt");
+
+    let mut cache: CachedEEPROM<_, _, 4> = CachedEEPROM::new(mcu.eeprom(layout(0, 2)));
+    cache.write(1, 0x1234).unwrap();
+    cache.eeprom().compact().unwrap();
+    cache.try_flush().unwrap();
+    assert!(!cache.is_dirty());
+    assert_eq!(0x1234, cache.into_inner().read(1).unwrap());
+}
@@ -0,0 +1,109 @@
+use crate::{EEPROMExt, FileFlash, Flash, MirroredEEPROM, Params};
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+fn params() -> Params {
+    Params {
+        first_page: 2,
+        flash_size: 64 * 1024,
+        page_size: 1024,
+        page_count: 2,
+    }
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("eeprom-{}-{}.bin", name, process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn test_file_created_erased() {
+    let path = temp_path("created");
+    let params = params();
+    FileFlash::open(&path, &params).unwrap();
+
+    let data = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(2048, data.len());
+    assert!(data.iter().all(|&b| b == 0xff));
+}
+
+#[test]
+fn test_file_nor_semantics() {
+    let path = temp_path("nor");
+    let params = params();
+    let mut file = FileFlash::open(&path, &params).unwrap();
+    let mut flash = &mut file;
+
+    flash.write(&params, 2048, 0xabcd).unwrap();
+    assert_eq!(0xabcd, flash.read(&params, 2048).unwrap());
+
+    // Programming can only clear bits
+    flash.write(&params, 2048, 0xff00).unwrap();
+    assert_eq!(0xab00, flash.read(&params, 2048).unwrap());
+
+    // Offsets outside of the EEPROM region are rejected
+    assert!(flash.read(&params, 0).is_err());
+    assert!(flash.write(&params, 4096, 0).is_err());
+    assert!(flash.page_erase(&params, 2050).is_err());
+
+    flash.page_erase(&params, 2048).unwrap();
+    assert_eq!(0xffff, flash.read(&params, 2048).unwrap());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_file_persists() {
+    let path = temp_path("persists");
+    let params = params();
+    {
+        let mut file = FileFlash::open(&path, &params).unwrap();
+        let mut eeprom = file.eeprom(params);
+        eeprom.init().unwrap();
+        eeprom.write(1, 0xdead).unwrap();
+        eeprom.write(2, 0xbeef).unwrap();
+    }
+
+    let mut file = FileFlash::open(&path, &params).unwrap();
+    let mut eeprom = file.eeprom(params);
+    eeprom.init().unwrap();
+    assert_eq!(0xdead, eeprom.read(1).unwrap());
+    assert_eq!(0xbeef, eeprom.read(2).unwrap());
+    assert!(eeprom.read(3).is_none());
+    fs::remove_file(&path).unwrap();
+}
+
+// Both regions of the mirrored EEPROM are stored in the same file
+#[test]
+fn test_file_two_regions() {
+    let path = temp_path("regions");
+    let region = |first_page| Params {
+        first_page,
+        flash_size: 64 * 1024,
+        page_size: 1024,
+        page_count: 2,
+    };
+    let params = Params {
+        page_count: 4,
+        ..region(0)
+    };
+    let mut file = FileFlash::open(&path, &params).unwrap();
+    {
+        let mut eeprom = MirroredEEPROM::new(region(0), region(2), &mut file);
+        eeprom.init().unwrap();
+        eeprom.write(1, 0xdead).unwrap();
+    }
+    let data = fs::read(&path).unwrap();
+    assert_eq!(4096, data.len());
+    assert!(data[2048..].iter().any(|&b| b != 0xff));
+
+    // Corrupt the value in the primary region (following the transaction record), so it is
+    // repaired from the secondary one
+    (&mut file).write(&params, 4 * 4 + 2, 0).unwrap();
+    assert_eq!(0, file.eeprom(region(0)).read(1).unwrap());
+    let mut eeprom = MirroredEEPROM::new(region(0), region(2), &mut file);
+    assert_eq!(Some(0xdead), eeprom.read(1).unwrap());
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,139 @@
+use super::{layout, MockFlash};
+use crate::{EEPROMExt, Error, MirroredEEPROM, EEPROM};
+
+fn mirrored(mcu: &mut MockFlash) -> MirroredEEPROM<&mut MockFlash> {
+    MirroredEEPROM::new(layout(0, 2), layout(2, 2), mcu)
+}
+
+fn written() -> MockFlash {
+    let mut mcu = MockFlash::erased(1024, 4);
+    let mut eeprom = mirrored(&mut mcu);
+    eeprom.init().unwrap();
+    eeprom.write(1, 0xdead).unwrap();
+    eeprom.write(2, 0xbeef).unwrap();
+    mcu
+}
+
+// Offset (in half-words) of the data of the value stored in the region, every value is written
+// by a transaction (record, value and checksum)
+fn value_data(first_page: u32, index: u32) -> usize {
+    (first_page * 512 + 9 + index * 6) as usize
+}
+
+#[test]
+fn test_mirror_write() {
+    let mut mcu = written();
+    for first_page in [0, 2] {
+        let mut eeprom = mcu.eeprom(layout(first_page, 2));
+        assert_eq!(0xdead, eeprom.read(1).unwrap());
+        assert_eq!(0xbeef, eeprom.read(2).unwrap());
+        assert_eq!(4, eeprom.live_tags());
+    }
+
+    let mut eeprom = mirrored(&mut mcu);
+    assert_eq!(Some(0xdead), eeprom.read(1).unwrap());
+    assert_eq!(Some(0xbeef), eeprom.read(2).unwrap());
+    assert_eq!(None, eeprom.read(3).unwrap());
+}
+
+#[test]
+fn test_mirror_repair_primary() {
+    let mut mcu = written();
+    mcu.flash_mem[value_data(0, 0)] = 0xdeac;
+
+    assert_eq!(Some(0xdead), mirrored(&mut mcu).read(1).unwrap());
+    assert_eq!(0xdead, mcu.eeprom(layout(0, 2)).read(1).unwrap());
+}
+
+#[test]
+fn test_mirror_repair_secondary() {
+    let mut mcu = written();
+    mcu.flash_mem[value_data(2, 0)] = 0xdeac;
+
+    mirrored(&mut mcu).init().unwrap();
+    assert_eq!(0xdead, mcu.eeprom(layout(2, 2)).read(1).unwrap());
+}
+
+// Write to the secondary region was interrupted, primary region is newer
+#[test]
+fn test_mirror_diverged() {
+    let mut mcu = written();
+    let mut primary = EEPROM::new(layout(0, 2), &mut mcu);
+    primary.write(1, 0x1234).unwrap();
+    primary.write(0x4001, 0x0956).unwrap();
+
+    mirrored(&mut mcu).init().unwrap();
+    assert_eq!(0x1234, mcu.eeprom(layout(2, 2)).read(1).unwrap());
+}
+
+// Write to the primary region was interrupted before checksum was written
+#[test]
+fn test_mirror_rolled_back() {
+    let mut mcu = written();
+    mcu.eeprom(layout(0, 2)).write(1, 0x1234).unwrap();
+
+    assert_eq!(Some(0xdead), mirrored(&mut mcu).read(1).unwrap());
+    assert_eq!(0xdead, mcu.eeprom(layout(0, 2)).read(1).unwrap());
+}
+
+#[test]
+fn test_mirror_rebuilt() {
+    let mut mcu = written();
+    // Magic of the active page header
+    mcu.flash_mem[2] = 0x1234;
+
+    let mut eeprom = mirrored(&mut mcu);
+    eeprom.init().unwrap();
+    assert_eq!(Some(0xdead), eeprom.read(1).unwrap());
+    assert_eq!(0xbeef, mcu.eeprom(layout(0, 2)).read(2).unwrap());
+}
+
+#[test]
+fn test_mirror_corrupted() {
+    let mut mcu = written();
+    mcu.flash_mem[value_data(0, 0)] = 0xdeac;
+    mcu.flash_mem[value_data(2, 0)] = 0xdeac;
+
+    let mut eeprom = mirrored(&mut mcu);
+    assert_eq!(Err(Error::Corrupted), eeprom.read(1));
+    assert_eq!(Some(0xbeef), eeprom.read(2).unwrap());
+}
+
+// Copy which is missing cannot be used to tell that the corrupted copy was never written
+#[test]
+fn test_mirror_corrupted_missing() {
+    let mut mcu = written();
+    mcu.eeprom(layout(2, 2)).erase().unwrap();
+    mcu.flash_mem[value_data(0, 0)] = 0xdeac;
+
+    assert_eq!(Err(Error::Corrupted), mirrored(&mut mcu).read(1));
+}
+
+#[test]
+fn test_mirror_init_corrupted() {
+    let mut mcu = written();
+    mcu.flash_mem[value_data(0, 0)] = 0xdeac;
+    mcu.flash_mem[value_data(2, 0)] = 0xdeac;
+    mcu.flash_mem[value_data(2, 1)] = 0xbeee;
+
+    let mut eeprom = mirrored(&mut mcu);
+    eeprom.init().unwrap();
+    assert_eq!(Err(Error::Corrupted), eeprom.read(1));
+    assert_eq!(0xbeef, mcu.eeprom(layout(2, 2)).read(2).unwrap());
+}
+
+// Write to the primary region was interrupted before checksum was written, so the incomplete
+// transaction is discarded
+#[test]
+fn test_mirror_interrupted() {
+    let mut mcu = written();
+    mirrored(&mut mcu).write(1, 0x1234).unwrap();
+    // Checksum of the value written to the primary region
+    mcu.flash_mem[22] = 0xffff;
+    mcu.flash_mem[23] = 0xffff;
+
+    let mut eeprom = mirrored(&mut mcu);
+    eeprom.init().unwrap();
+    assert_eq!(Some(0xdead), eeprom.read(1).unwrap());
+    assert_eq!(0xdead, mcu.eeprom(layout(2, 2)).read(1).unwrap());
+}
@@ -1,9 +1,27 @@
+// Tests predate some of the clippy lints
+#![allow(
+    clippy::bool_assert_comparison,
+    clippy::get_first,
+    clippy::needless_lifetimes,
+    clippy::ptr_arg
+)]
+
 use super::EEPROM;
-use crate::{EEPROMExt, Flash, FlashResult, HalfWord, Params};
+use crate::{
+    ConstLayout, Diagnostics, EEPROMExt, Error, Flash, FlashResult, HalfWord, PageReport,
+    PageState, Params, ParamsError, Report, ERASE_RETRIES, PROGRAM_RETRIES,
+};
 use std::mem::size_of;
 use std::vec::Vec;
 
+mod cache;
+#[cfg(feature = "std")]
+mod file;
 mod memdump;
+mod mirror;
+#[cfg(all(feature = "mtd", target_os = "linux"))]
+mod mtd;
+mod reader;
 
 // Fake linker variables
 #[export_name = "_eeprom_start"]
@@ -24,6 +42,18 @@ struct MockFlash {
     flash_mem: Vec<u16>,
     page_size: u32,
     page_count: u32,
+    // Amount of page erases performed
+    erases: u32,
+    // Amount of reads performed (of a single half-word or a slice)
+    reads: u32,
+    // Emulate NOR flash, which allows clearing bits of programmed half-words
+    clears_bits: bool,
+    // Amount of the next writes which store wrong data
+    faulty_writes: u32,
+    // Amount of writes which store correct data before the faulty ones
+    good_writes: u32,
+    // Amount of the next erases which leave the page dirty
+    faulty_erases: u32,
 }
 
 // Emulate MCU flash memory & FLASH control registers
@@ -37,27 +67,79 @@ impl MockFlash {
             flash_mem,
             page_size,
             page_count,
+            erases: 0,
+            reads: 0,
+            clears_bits: false,
+            faulty_writes: 0,
+            good_writes: 0,
+            faulty_erases: 0,
+        }
+    }
+
+    fn erased(page_size: u32, page_count: u32) -> MockFlash {
+        let size = page_size * page_count / (size_of::<u16>() as u32);
+        MockFlash {
+            flash_mem: vec![0xffff; size as usize],
+            page_size,
+            page_count,
+            erases: 0,
+            reads: 0,
+            clears_bits: false,
+            faulty_writes: 0,
+            good_writes: 0,
+            faulty_erases: 0,
         }
     }
 }
 
 impl<'a> Flash for &'a mut MockFlash {
     fn read(&mut self, _params: &Params, offset: u32) -> FlashResult<HalfWord> {
+        self.reads += 1;
         Ok(self.flash_mem[(offset / 2) as usize])
     }
 
+    fn read_slice(
+        &mut self,
+        _params: &Params,
+        offset: u32,
+        data: &mut [HalfWord],
+    ) -> FlashResult<()> {
+        self.reads += 1;
+        let start = (offset / 2) as usize;
+        data.copy_from_slice(&self.flash_mem[start..start + data.len()]);
+        Ok(())
+    }
+
     fn page_erase(&mut self, _params: &Params, offset: u32) -> FlashResult<()> {
         assert_eq!(offset % self.page_size, 0);
+        self.erases += 1;
         for i in 0..(self.page_size / 2) {
             self.flash_mem[((offset / 2) + i) as usize] = 0xffff;
         }
+        if self.faulty_erases != 0 {
+            self.faulty_erases -= 1;
+            self.flash_mem[(offset / 2) as usize] = 0;
+        }
         Ok(())
     }
 
     fn write(&mut self, _params: &Params, offset: u32, data: u16) -> FlashResult<()> {
+        let data = if self.good_writes != 0 {
+            self.good_writes -= 1;
+            data
+        } else if self.faulty_writes != 0 {
+            self.faulty_writes -= 1;
+            data | 0x0001
+        } else {
+            data
+        };
         self.flash_mem[(offset / 2) as usize] = data;
         Ok(())
     }
+
+    fn clears_bits(&self) -> bool {
+        self.clears_bits
+    }
 }
 
 impl<'a> EEPROMExt for &'a mut MockFlash {
@@ -66,23 +148,37 @@ impl<'a> EEPROMExt for &'a mut MockFlash {
     }
 }
 
-fn test(initial: &str, expected: &str, cb: for<'a> fn(&mut EEPROM<&'a mut MockFlash>)) {
+fn assert_flash(mcu: &MockFlash, expected: &str) {
+    let expected_file = memdump::read_file(expected);
+    let expected: Vec<&str> = expected_file.lines().collect();
+    let actual_dump = memdump::dump(&mcu.flash_mem, mcu.page_size);
+    let actual_lines: Vec<&str> = actual_dump.lines().collect();
+    assert_eq!(expected, actual_lines);
+}
+
+fn test<F>(initial: &str, expected: &str, cb: F)
+where
+    F: FnOnce(&mut EEPROM<&mut MockFlash>),
+{
     let mut mcu = MockFlash::load(initial, 1024, 2);
     let params = Params {
         first_page: 0,
         flash_size: 64 * 1024,
-        page_size: 1,
+        page_size: 1024,
         page_count: mcu.page_count,
     };
     let mut eeprom = mcu.eeprom(params);
 
     cb(&mut eeprom);
 
-    let expected_file = memdump::read_file(expected);
-    let expected: Vec<&str> = expected_file.lines().collect();
-    let actual_dump = memdump::dump(&mcu.flash_mem, mcu.page_size);
-    let actual_lines: Vec<&str> = actual_dump.lines().collect();
-    assert_eq!(expected, actual_lines);
+    assert_flash(&mcu, expected);
+}
+
+// Check that `init` fails with a given error, without touching the flash
+fn test_init_error(initial: &str, error: Error) {
+    test(initial, initial, |eeprom| {
+        assert_eq!(Err(error), eeprom.init())
+    })
 }
 
 fn test_init(initial: &str, expected: &str) {
@@ -160,6 +256,152 @@ fn test_init_rescue_full_simple_duplicated() {
     )
 }
 
+// Rescue was interrupted after marking the target page as active, but before erasing the source
+#[test]
+fn test_init_two_active_rescue_interrupted() {
+    test_init(
+        "src/tests/test-data/two-active-rescue-interrupted.txt",
+        "src/tests/test-data/valid-simple-page2.txt",
+    )
+}
+
+#[test]
+fn test_init_unknown_format() {
+    test_init_error(
+        "src/tests/test-data/unknown-format.txt",
+        Error::UnknownFormat,
+    )
+}
+
+#[test]
+fn test_init_unsupported_version() {
+    test_init_error(
+        "src/tests/test-data/unsupported-version.txt",
+        Error::UnsupportedVersion(2),
+    )
+}
+
+#[test]
+fn test_init_params_mismatch() {
+    test_init_error(
+        "src/tests/test-data/params-mismatch.txt",
+        Error::ParamsMismatch,
+    )
+}
+
+// init() migration from eeprom 0.3 format
+
+#[test]
+fn test_init_legacy_empty() {
+    test_init(
+        "src/tests/test-data/legacy/empty.txt",
+        "src/tests/test-data/empty-page2.txt",
+    )
+}
+
+#[test]
+fn test_init_legacy_empty_page2() {
+    test_init(
+        "src/tests/test-data/legacy/empty-page2.txt",
+        "src/tests/test-data/empty.txt",
+    )
+}
+
+#[test]
+fn test_init_legacy_two_empty_current() {
+    test_init(
+        "src/tests/test-data/legacy/two-empty-current-pages.txt",
+        "src/tests/test-data/empty-page2.txt",
+    )
+}
+
+// Note that order is reversed when migrated (since we scan from the end)
+#[test]
+fn test_init_legacy_valid_simple() {
+    test_init(
+        "src/tests/test-data/legacy/valid-simple.txt",
+        "src/tests/test-data/migrated-simple-page2.txt",
+    )
+}
+
+#[test]
+fn test_init_legacy_full_bogus() {
+    test_init(
+        "src/tests/test-data/legacy/full-bogus.txt",
+        "src/tests/test-data/valid-simple.txt",
+    )
+}
+
+#[test]
+fn test_init_legacy_full_bogus_duplicated() {
+    test_init(
+        "src/tests/test-data/legacy/full-bogus-duplicated-data.txt",
+        "src/tests/test-data/valid-simple.txt",
+    )
+}
+
+#[test]
+fn test_init_legacy_full_simple() {
+    test_init(
+        "src/tests/test-data/legacy/full-simple.txt",
+        "src/tests/test-data/valid-simple.txt",
+    )
+}
+
+// Target page was marked as active, but legacy page was not erased yet
+#[test]
+fn test_init_legacy_migration_interrupted() {
+    test_init(
+        "src/tests/test-data/legacy/migration-interrupted.txt",
+        "src/tests/test-data/migrated-simple-page2.txt",
+    )
+}
+
+// Values were copied to the target page, but it was not marked as active yet
+#[test]
+fn test_init_legacy_migration_not_activated() {
+    test_init(
+        "src/tests/test-data/legacy/migration-not-activated.txt",
+        "src/tests/test-data/migrated-simple-page2.txt",
+    )
+}
+
+#[test]
+fn test_read_legacy_migrated() {
+    let mut mcu = MockFlash::load("src/tests/test-data/legacy/full-simple.txt", 1024, 2);
+    let params = Params {
+        first_page: 0,
+        flash_size: 64 * 1024,
+        page_size: 1024,
+        page_count: mcu.page_count,
+    };
+    let mut eeprom = mcu.eeprom(params);
+    eeprom.init().unwrap();
+
+    assert_eq!(0xdead, eeprom.read(1).unwrap());
+    assert_eq!(0xbeef, eeprom.read(2).unwrap());
+    assert!(eeprom.read(3).is_none());
+}
+
+// Legacy page holds more distinct tags than the page in the current format could fit
+#[test]
+fn test_init_legacy_too_many() {
+    let mut mcu = MockFlash::erased(1024, 2);
+    mcu.flash_mem[0] = 0xabcd;
+    for item in 1..255 {
+        mcu.flash_mem[item * 2] = item as HalfWord;
+        mcu.flash_mem[item * 2 + 1] = 0x1234;
+    }
+    let legacy = mcu.flash_mem[..512].to_vec();
+
+    assert_eq!(
+        Err(Error::CapacityExceeded),
+        mcu.eeprom(layout(0, 2)).init()
+    );
+    // Legacy page is kept intact
+    assert_eq!(legacy, mcu.flash_mem[..512]);
+}
+
 // erase() tests
 
 #[test]
@@ -186,6 +428,14 @@ fn test_erase_simple() {
     )
 }
 
+#[test]
+fn test_erase_unknown_format() {
+    test_erase(
+        "src/tests/test-data/unknown-format.txt",
+        "src/tests/test-data/empty.txt",
+    )
+}
+
 #[test]
 fn test_erase_full_simple() {
     test_erase(
@@ -201,7 +451,7 @@ fn test_read_full_simple() {
     let params = Params {
         first_page: 0,
         flash_size: 64 * 1024,
-        page_size: 1,
+        page_size: 1024,
         page_count: mcu.page_count,
     };
     let mut eeprom = mcu.eeprom(params);
@@ -222,7 +472,7 @@ fn test_read_full_simple_duplicated() {
     let params = Params {
         first_page: 0,
         flash_size: 64 * 1024,
-        page_size: 1,
+        page_size: 1024,
         page_count: mcu.page_count,
     };
     let mut eeprom = mcu.eeprom(params);
@@ -232,6 +482,29 @@ fn test_read_full_simple_duplicated() {
     assert_eq!(true, eeprom.read(3).is_none());
 }
 
+// Pages are scanned with slice reads
+#[test]
+fn test_read_slices() {
+    let mut mcu = MockFlash::load("src/tests/test-data/valid-simple.txt", 1024, 2);
+    assert!(mcu.eeprom(layout(0, 2)).read(3).is_none());
+    // Status of the active page, then 253 items in chunks of 16
+    assert_eq!(1 + 16, mcu.reads);
+
+    mcu.reads = 0;
+    mcu.eeprom(layout(0, 2)).prepare().unwrap();
+    // Status of the active and the spare page, then 256 items of the spare page in chunks of 16
+    assert_eq!(2 + 16, mcu.reads);
+}
+
+// Lookup of the free item and counting of the live values scan the page in chunks as well
+#[test]
+fn test_write_slices() {
+    let mut mcu = MockFlash::load("src/tests/test-data/valid-simple.txt", 1024, 2);
+    mcu.eeprom(layout(0, 2)).write(3, 0xacdb).unwrap();
+    // A few full scans of 253 items, rather than a read per item
+    assert!(mcu.reads < 64, "{} reads", mcu.reads);
+}
+
 // write() tests
 #[test]
 fn test_write_empty() {
@@ -249,7 +522,7 @@ fn test_write_empty() {
 fn test_write_rescue() {
     test(
         "src/tests/test-data/full-bogus.txt",
-        "src/tests/test-data/valid-simple-third.txt",
+        "src/tests/test-data/rescued-full-bogus.txt",
         |eeprom| {
             eeprom.write(3, 0xacdb).unwrap();
         },
@@ -260,9 +533,1148 @@ fn test_write_rescue() {
 fn test_write_rescue_duplicated() {
     test(
         "src/tests/test-data/full-simple.txt",
-        "src/tests/test-data/valid-simple-third.txt",
+        "src/tests/test-data/rescued-full-simple.txt",
         |eeprom| {
             eeprom.write(3, 0xacdb).unwrap();
         },
     );
 }
+
+#[test]
+fn test_write_rescue_without_erase() {
+    let mut mcu = MockFlash::load("src/tests/test-data/full-simple.txt", 1024, 2);
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    eeprom.prepare().unwrap();
+    eeprom.write(3, 0xacdb).unwrap();
+    assert_eq!(0, mcu.erases);
+}
+
+#[test]
+fn test_write_rescue_unprepared() {
+    let mut mcu = MockFlash::load("src/tests/test-data/rescued-full-simple.txt", 1024, 2);
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    // Fill the page, so the next write has to compact back to the obsolete page
+    for value in 0..250 {
+        eeprom.write(4, value).unwrap();
+    }
+    eeprom.write(3, 0xacdb).unwrap();
+    assert_eq!(1, mcu.erases);
+    assert_eq!(0xacdb, mcu.eeprom(layout(0, 2)).read(3).unwrap());
+}
+
+// update() tests
+
+#[test]
+fn test_update_unchanged() {
+    test(
+        "src/tests/test-data/valid-simple.txt",
+        "src/tests/test-data/valid-simple.txt",
+        |eeprom| {
+            assert!(!eeprom.update(1, 0xdead).unwrap());
+            assert!(!eeprom.update(2, 0xbeef).unwrap());
+        },
+    );
+}
+
+#[test]
+fn test_update_changed() {
+    test(
+        "src/tests/test-data/empty.txt",
+        "src/tests/test-data/valid-simple.txt",
+        |eeprom| {
+            assert!(eeprom.update(1, 0xdead).unwrap());
+            assert!(eeprom.update(2, 0xbeef).unwrap());
+            assert!(!eeprom.update(1, 0xdead).unwrap());
+        },
+    );
+}
+
+// try_write() tests
+
+#[test]
+fn test_try_write() {
+    test(
+        "src/tests/test-data/empty.txt",
+        "src/tests/test-data/valid-simple.txt",
+        |eeprom| {
+            assert!(!eeprom.needs_compaction());
+            eeprom.try_write(1, 0xdead).unwrap();
+            eeprom.try_write(2, 0xbeef).unwrap();
+        },
+    );
+}
+
+#[test]
+fn test_try_write_full() {
+    test(
+        "src/tests/test-data/full-bogus.txt",
+        "src/tests/test-data/full-bogus.txt",
+        |eeprom| {
+            assert!(eeprom.needs_compaction());
+            assert_eq!(Err(Error::WouldCompact), eeprom.try_write(3, 0xacdb));
+        },
+    );
+}
+
+#[test]
+fn test_try_write_compact() {
+    test(
+        "src/tests/test-data/full-bogus.txt",
+        "src/tests/test-data/rescued-full-bogus.txt",
+        |eeprom| {
+            eeprom.compact().unwrap();
+            assert!(!eeprom.needs_compaction());
+            eeprom.try_write(3, 0xacdb).unwrap();
+        },
+    );
+}
+
+// clear_bits() tests
+
+#[test]
+fn test_clear_bits_appended() {
+    test(
+        "src/tests/test-data/valid-simple.txt",
+        "src/tests/test-data/cleared-appended.txt",
+        |eeprom| {
+            assert_eq!(0xde00, eeprom.clear_bits(1, 0x00ff).unwrap());
+            assert_eq!(0xfffe, eeprom.clear_bits(3, 0x0001).unwrap());
+            assert_eq!(0xde00, eeprom.read(1).unwrap());
+            assert_eq!(0xfffe, eeprom.read(3).unwrap());
+        },
+    );
+}
+
+#[test]
+fn test_clear_bits_unchanged() {
+    test(
+        "src/tests/test-data/valid-simple.txt",
+        "src/tests/test-data/valid-simple.txt",
+        |eeprom| assert_eq!(0xdead, eeprom.clear_bits(1, 0x0010).unwrap()),
+    );
+}
+
+// STM32 allows programming zero over a programmed half-word
+#[test]
+fn test_clear_bits_zeroed() {
+    test(
+        "src/tests/test-data/valid-simple.txt",
+        "src/tests/test-data/cleared-zeroed.txt",
+        |eeprom| {
+            assert_eq!(0, eeprom.clear_bits(2, 0xffff).unwrap());
+            assert_eq!(0, eeprom.read(2).unwrap());
+        },
+    );
+}
+
+#[test]
+fn test_clear_bits_in_place() {
+    let mut mcu = MockFlash::load("src/tests/test-data/valid-simple.txt", 1024, 2);
+    mcu.clears_bits = true;
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    for bit in 0..16 {
+        eeprom.clear_bits(2, 1 << bit).unwrap();
+    }
+    assert_eq!(0, eeprom.read(2).unwrap());
+    eeprom.clear_bits(1, 0x00ff).unwrap();
+    assert_flash(&mcu, "src/tests/test-data/cleared-in-place.txt");
+}
+
+#[test]
+fn test_clear_bits_compact_step() {
+    let mut mcu = MockFlash::load("src/tests/test-data/valid-simple.txt", 1024, 2);
+    mcu.clears_bits = true;
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    assert!(!eeprom.compact_step(1).unwrap());
+    // Value is already copied to the target page, so it cannot be updated in place
+    eeprom.clear_bits(1, 0x00ff).unwrap();
+    eeprom.clear_bits(2, 0x00ff).unwrap();
+    while !eeprom.compact_step(1).unwrap() {}
+    assert_eq!(0xde00, eeprom.read(1).unwrap());
+    assert_eq!(0xbe00, eeprom.read(2).unwrap());
+    assert_eq!(2, eeprom.live_tags());
+}
+
+// increment() tests
+
+#[test]
+fn test_increment() {
+    test(
+        "src/tests/test-data/empty.txt",
+        "src/tests/test-data/counter-simple.txt",
+        |eeprom| {
+            assert_eq!(0, eeprom.counter(1));
+            assert_eq!(1, eeprom.increment(1).unwrap());
+            assert_eq!(2, eeprom.increment(1).unwrap());
+            assert_eq!(3, eeprom.increment(1).unwrap());
+            assert_eq!(3, eeprom.counter(1));
+            assert_eq!(2, eeprom.live_tags());
+        },
+    );
+}
+
+#[test]
+fn test_increment_carry() {
+    test(
+        "src/tests/test-data/empty.txt",
+        "src/tests/test-data/counter-carry.txt",
+        |eeprom| {
+            eeprom.write(1, 0xfffe).unwrap();
+            assert_eq!(0xffff, eeprom.increment(1).unwrap());
+            assert_eq!(0x1_0000, eeprom.increment(1).unwrap());
+            assert_eq!(0x1_0001, eeprom.increment(1).unwrap());
+            assert_eq!(0x1_0001, eeprom.counter(1));
+            assert_eq!(0x0001, eeprom.read(1).unwrap());
+            assert_eq!(2, eeprom.live_tags());
+        },
+    );
+}
+
+#[test]
+fn test_increment_written() {
+    let mut mcu = MockFlash::load("src/tests/test-data/valid-simple.txt", 1024, 2);
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    assert_eq!(0xdeae, eeprom.increment(1).unwrap());
+    assert_eq!(0xbeef, eeprom.counter(2));
+    assert_eq!(3, eeprom.live_tags());
+}
+
+#[test]
+fn test_increment_large() {
+    let mut mcu = MockFlash::load("src/tests/test-data/counter-large.txt", 1024, 2);
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    assert_eq!(0xffff_fff1, eeprom.counter(1));
+    for _ in 0..14 {
+        eeprom.increment(1).unwrap();
+    }
+    assert_eq!(u32::MAX, eeprom.counter(1));
+}
+
+#[test]
+#[should_panic(expected = "counter overflow")]
+fn test_increment_overflow() {
+    let mut mcu = MockFlash::load("src/tests/test-data/counter-large.txt", 1024, 2);
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    for _ in 0..15 {
+        let _ = eeprom.increment(1);
+    }
+}
+
+// Reset happened before the tag of the counter value was written
+#[test]
+fn test_increment_interrupted() {
+    let mut mcu = MockFlash::load("src/tests/test-data/counter-interrupted.txt", 1024, 2);
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    eeprom.init().unwrap();
+    assert_eq!(3, eeprom.counter(1));
+    assert_eq!(0, eeprom.counter(2));
+    eeprom.write(2, 0xbeef).unwrap();
+    assert_eq!(3, eeprom.counter(1));
+    assert_eq!(0xbeef, eeprom.counter(2));
+    assert_eq!(4, eeprom.increment(1).unwrap());
+    assert_eq!(3, eeprom.live_tags());
+}
+
+// Value written after the interrupted increment replaces the counter
+#[test]
+fn test_increment_interrupted_write() {
+    let mut mcu = MockFlash::load("src/tests/test-data/counter-interrupted.txt", 1024, 2);
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    eeprom.write(1, 100).unwrap();
+    assert_eq!(100, eeprom.read(1).unwrap());
+    assert_eq!(100, eeprom.counter(1));
+    eeprom.compact().unwrap();
+    assert_eq!(100, eeprom.read(1).unwrap());
+    assert_eq!(101, eeprom.increment(1).unwrap());
+}
+
+#[test]
+fn test_increment_compact() {
+    let mut mcu = MockFlash::load("src/tests/test-data/counter-simple.txt", 1024, 2);
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    eeprom.compact().unwrap();
+    assert_eq!(3, eeprom.counter(1));
+    while !eeprom.compact_step(1).unwrap() {}
+    assert_eq!(3, eeprom.counter(1));
+    assert_eq!(2, eeprom.live_tags());
+}
+
+#[test]
+fn test_increment_capacity() {
+    let mut mcu = MockFlash::erased(128, 2);
+    let params = Params {
+        first_page: 0,
+        flash_size: 64 * 1024,
+        page_size: 128,
+        page_count: 2,
+    };
+    let mut eeprom = EEPROM::try_new(params, &mut mcu).unwrap();
+    eeprom.init().unwrap();
+    for tag in 0..26 {
+        eeprom.write(tag, tag).unwrap();
+    }
+    assert_eq!(Err(Error::CapacityExceeded), eeprom.increment(26));
+
+    // Counter keeps working while the page is compacted over and over
+    eeprom.increment(0).unwrap();
+    for value in 2..100 {
+        assert_eq!(value, eeprom.increment(0).unwrap());
+    }
+    for tag in 1..26 {
+        assert_eq!(u32::from(tag), eeprom.counter(tag));
+    }
+    assert_eq!(27, eeprom.live_tags());
+}
+
+// transaction() tests
+
+#[test]
+fn test_transaction() {
+    test(
+        "src/tests/test-data/empty.txt",
+        "src/tests/test-data/transaction-simple.txt",
+        |eeprom| {
+            eeprom
+                .transaction(|tx| {
+                    tx.write(1, 0x1111);
+                    tx.write(2, 0xbeef);
+                    tx.write(1, 0xdead);
+                })
+                .unwrap();
+            assert_eq!(0xdead, eeprom.read(1).unwrap());
+            assert_eq!(0xbeef, eeprom.read(2).unwrap());
+            assert_eq!(2, eeprom.live_tags());
+        },
+    );
+}
+
+#[test]
+fn test_transaction_empty() {
+    test(
+        "src/tests/test-data/valid-simple.txt",
+        "src/tests/test-data/valid-simple.txt",
+        |eeprom| eeprom.transaction(|_| ()).unwrap(),
+    );
+}
+
+#[test]
+fn test_init_transaction_complete() {
+    test_init(
+        "src/tests/test-data/transaction-simple.txt",
+        "src/tests/test-data/transaction-simple.txt",
+    )
+}
+
+// Reset happened before the second value of the transaction was written
+#[test]
+fn test_init_transaction_interrupted() {
+    test_init(
+        "src/tests/test-data/transaction-interrupted.txt",
+        "src/tests/test-data/transaction-discarded.txt",
+    )
+}
+
+// Reset happened while the second value of the transaction was written
+#[test]
+fn test_init_transaction_torn() {
+    test_init(
+        "src/tests/test-data/transaction-torn.txt",
+        "src/tests/test-data/transaction-discarded.txt",
+    )
+}
+
+// Value of the transaction failed to verify and was marked invalid
+#[test]
+fn test_init_transaction_invalid() {
+    test_init(
+        "src/tests/test-data/transaction-invalid.txt",
+        "src/tests/test-data/transaction-discarded.txt",
+    )
+}
+
+#[test]
+fn test_transaction_compact() {
+    let mut mcu = MockFlash::load("src/tests/test-data/transaction-simple.txt", 1024, 2);
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    eeprom.compact().unwrap();
+    assert_eq!(0xdead, eeprom.read(1).unwrap());
+    assert_eq!(0xbeef, eeprom.read(2).unwrap());
+    assert_eq!(2, eeprom.live_tags());
+}
+
+#[test]
+fn test_transaction_rescue() {
+    test(
+        "src/tests/test-data/full-simple.txt",
+        "src/tests/test-data/transaction-rescued.txt",
+        |eeprom| {
+            eeprom
+                .transaction(|tx| {
+                    tx.write(1, 0x1234);
+                    tx.write(3, 0xacdb);
+                })
+                .unwrap();
+            assert_eq!(0x1234, eeprom.read(1).unwrap());
+            assert_eq!(0xbeef, eeprom.read(2).unwrap());
+            assert_eq!(0xacdb, eeprom.read(3).unwrap());
+        },
+    );
+}
+
+#[test]
+fn test_transaction_capacity() {
+    let mut mcu = MockFlash::erased(128, 2);
+    let params = Params {
+        first_page: 0,
+        flash_size: 64 * 1024,
+        page_size: 128,
+        page_count: 2,
+    };
+    let mut eeprom = EEPROM::try_new(params, &mut mcu).unwrap();
+    eeprom.init().unwrap();
+    for tag in 0..26 {
+        eeprom.write(tag, tag).unwrap();
+    }
+
+    // Transaction record and the values should fit into the page after compaction
+    let result = eeprom.transaction(|tx| {
+        tx.write(0, 0xdead);
+        tx.write(1, 0xdead);
+        tx.write(2, 0xdead);
+    });
+    assert_eq!(Err(Error::CapacityExceeded), result);
+    eeprom
+        .transaction(|tx| {
+            tx.write(0, 0xdead);
+            tx.write(26, 0xbeef);
+        })
+        .unwrap();
+    assert_eq!(0xdead, eeprom.read(0).unwrap());
+    assert_eq!(0xbeef, eeprom.read(26).unwrap());
+    assert_eq!(27, eeprom.live_tags());
+}
+
+#[test]
+#[should_panic(expected = "too many values in transaction")]
+fn test_transaction_too_many_values() {
+    let mut mcu = MockFlash::load("src/tests/test-data/empty.txt", 1024, 2);
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    let _ = eeprom.transaction(|tx| {
+        for tag in 0..=crate::MAX_TRANSACTION_ITEMS as u16 {
+            tx.write(tag, 0);
+        }
+    });
+}
+
+// Verify mode tests
+
+#[test]
+fn test_verify_write() {
+    let mut mcu = MockFlash::load("src/tests/test-data/valid-simple.txt", 1024, 2);
+    mcu.faulty_writes = 1;
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    eeprom.set_verify(true);
+    eeprom.write(3, 0xacda).unwrap();
+    assert_eq!(0xacda, eeprom.read(3).unwrap());
+    assert_eq!(3, eeprom.live_tags());
+    let diagnostics = Diagnostics {
+        program_failures: 1,
+        invalid_items: 1,
+        erase_retries: 0,
+        retired_pages: 0,
+    };
+    assert_eq!(diagnostics, eeprom.diagnostics());
+    assert_flash(&mcu, "src/tests/test-data/verified-invalid.txt");
+}
+
+#[test]
+fn test_verify_write_failed() {
+    let mut mcu = MockFlash::load("src/tests/test-data/valid-simple.txt", 1024, 2);
+    mcu.faulty_writes = 100;
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    eeprom.set_verify(true);
+    assert_eq!(Err(Error::Verify), eeprom.write(3, 0xacda));
+    assert_eq!(4, eeprom.diagnostics().invalid_items);
+}
+
+// Tag is written wrong, taking the value for a different tag
+#[test]
+fn test_verify_write_tag() {
+    let mut mcu = MockFlash::load("src/tests/test-data/valid-simple.txt", 1024, 2);
+    mcu.good_writes = 1;
+    mcu.faulty_writes = 1;
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    eeprom.set_verify(true);
+    eeprom.write(2, 0xbeee).unwrap();
+    assert_eq!(0xbeee, eeprom.read(2).unwrap());
+    assert!(eeprom.read(3).is_none());
+    assert_eq!(2, eeprom.live_tags());
+    assert_eq!(1, eeprom.diagnostics().invalid_items);
+}
+
+// Item with the wrong tag cannot be marked invalid
+#[test]
+fn test_verify_write_tag_failed() {
+    let mut mcu = MockFlash::load("src/tests/test-data/valid-simple.txt", 1024, 2);
+    mcu.good_writes = 1;
+    mcu.faulty_writes = 2;
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    eeprom.set_verify(true);
+    assert_eq!(Err(Error::Corrupted), eeprom.write(2, 0xbeee));
+    assert_eq!(1, eeprom.diagnostics().invalid_items);
+}
+
+// Counter tag is written after its record, so it is verified the same way
+#[test]
+fn test_verify_increment_tag() {
+    let mut mcu = MockFlash::load("src/tests/test-data/empty.txt", 1024, 2);
+    mcu.good_writes = 3;
+    mcu.faulty_writes = 1;
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    eeprom.set_verify(true);
+    assert_eq!(Err(Error::Verify), eeprom.increment(6));
+    assert!(eeprom.read(7).is_none());
+    assert_eq!(0, eeprom.counter(7));
+    assert_eq!(1, eeprom.diagnostics().invalid_items);
+    assert_eq!(1, eeprom.increment(6).unwrap());
+}
+
+// Value cannot be marked invalid when programmed in place, so it is appended instead
+#[test]
+fn test_verify_clear_bits() {
+    let mut mcu = MockFlash::load("src/tests/test-data/valid-simple.txt", 1024, 2);
+    mcu.clears_bits = true;
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    eeprom.write(5, 0x1110).unwrap();
+    eeprom.set_verify(true);
+    eeprom.flash.faulty_writes = 1;
+    assert_eq!(0x1010, eeprom.clear_bits(5, 0x0100).unwrap());
+    assert_eq!(0x1010, eeprom.read(5).unwrap());
+    assert_eq!(1, eeprom.diagnostics().invalid_items);
+}
+
+#[test]
+fn test_verify_disabled() {
+    let mut mcu = MockFlash::load("src/tests/test-data/valid-simple.txt", 1024, 2);
+    mcu.faulty_writes = 1;
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    eeprom.write(3, 0xacda).unwrap();
+    assert_eq!(0xacdb, eeprom.read(3).unwrap());
+    assert_eq!(Diagnostics::default(), eeprom.diagnostics());
+}
+
+#[test]
+fn test_verify_erase() {
+    let mut mcu = MockFlash::load("src/tests/test-data/valid-simple.txt", 1024, 2);
+    mcu.faulty_erases = 1;
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    eeprom.set_verify(true);
+    eeprom.erase().unwrap();
+    assert_eq!(1, eeprom.diagnostics().erase_retries);
+    assert_flash(&mcu, "src/tests/test-data/empty.txt");
+}
+
+#[test]
+fn test_verify_erase_failed() {
+    let mut mcu = MockFlash::load("src/tests/test-data/valid-simple.txt", 1024, 2);
+    mcu.faulty_erases = 100;
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    eeprom.set_verify(true);
+    assert_eq!(Err(Error::Verify), eeprom.erase());
+}
+
+// Bad page retirement tests
+
+#[test]
+fn test_retire_page() {
+    let mut mcu = MockFlash::erased(1024, 3);
+    let mut eeprom = EEPROM::new(layout(0, 3), &mut mcu);
+    eeprom.init().unwrap();
+    eeprom.write(1, 0xdead).unwrap();
+    eeprom.write(2, 0xbeef).unwrap();
+    eeprom.flash.flash_mem[512] = 0; // dirty next page
+    eeprom.flash.faulty_erases = 1 + ERASE_RETRIES;
+    eeprom.set_verify(true);
+
+    eeprom.compact().unwrap();
+    assert_eq!(Some(2), eeprom.find_active());
+    assert_eq!(1, eeprom.diagnostics().retired_pages);
+    assert_eq!(0xdead, eeprom.read(1).unwrap());
+    assert_eq!(0xbeef, eeprom.read(2).unwrap());
+
+    // Retired page is skipped by compaction
+    eeprom.compact().unwrap();
+    assert_eq!(Some(0), eeprom.find_active());
+    eeprom.prepare().unwrap();
+    eeprom.compact_step(10).unwrap();
+    assert_eq!(Some(2), eeprom.find_active());
+    eeprom.init().unwrap();
+    assert!(eeprom.is_bad_page(1));
+    assert_eq!(0xdead, eeprom.read(1).unwrap());
+
+    eeprom.erase().unwrap();
+    assert!(eeprom.is_bad_page(1));
+    assert_eq!(Some(0), eeprom.find_active());
+    eeprom.compact().unwrap();
+    assert_eq!(Some(2), eeprom.find_active());
+}
+
+// Two usable pages are required for compaction
+#[test]
+fn test_retire_page_refused() {
+    let mut mcu = MockFlash::erased(1024, 3);
+    let mut eeprom = EEPROM::new(layout(0, 3), &mut mcu);
+    eeprom.init().unwrap();
+    eeprom.set_verify(true);
+    eeprom.flash.faulty_erases = 1 + ERASE_RETRIES;
+    eeprom.flash.flash_mem[512] = 0;
+    eeprom.compact().unwrap();
+    assert_eq!(Some(2), eeprom.find_active());
+
+    eeprom.flash.faulty_erases = 1 + ERASE_RETRIES;
+    eeprom.flash.flash_mem[0] = 0;
+    assert_eq!(Err(Error::Verify), eeprom.compact());
+    assert_eq!(1, eeprom.diagnostics().retired_pages);
+    assert!(!eeprom.is_bad_page(0));
+}
+
+// Every failed attempt to write an item takes two faulty writes (value and invalid tag), values
+// have the lowest bit cleared, so faulty writes change them
+#[test]
+fn test_retire_page_program() {
+    let mut mcu = MockFlash::erased(1024, 3);
+    let mut eeprom = EEPROM::new(layout(0, 3), &mut mcu);
+    eeprom.init().unwrap();
+    eeprom.write(1, 0xdeac).unwrap();
+    eeprom.write(2, 0xbeee).unwrap();
+    eeprom.set_verify(true);
+    eeprom.flash.faulty_writes = 2 * (1 + PROGRAM_RETRIES);
+
+    eeprom.write(3, 0xacda).unwrap();
+    assert_eq!(Some(1), eeprom.find_active());
+    assert!(eeprom.is_bad_page(0));
+    assert_eq!(1, eeprom.diagnostics().retired_pages);
+    assert_eq!(0xdeac, eeprom.read(1).unwrap());
+    assert_eq!(0xbeee, eeprom.read(2).unwrap());
+    assert_eq!(0xacda, eeprom.read(3).unwrap());
+}
+
+#[test]
+fn test_retire_page_compaction() {
+    let mut mcu = MockFlash::erased(1024, 3);
+    let mut eeprom = EEPROM::new(layout(0, 3), &mut mcu);
+    eeprom.init().unwrap();
+    eeprom.write(1, 0xdeac).unwrap();
+    eeprom.write(2, 0xbeee).unwrap();
+    eeprom.set_verify(true);
+    eeprom.flash.faulty_writes = 2 * (1 + PROGRAM_RETRIES);
+
+    eeprom.compact().unwrap();
+    assert_eq!(Some(2), eeprom.find_active());
+    assert!(eeprom.is_bad_page(1));
+    assert_eq!(1, eeprom.diagnostics().retired_pages);
+    assert_eq!(0xdeac, eeprom.read(1).unwrap());
+    assert_eq!(0xbeee, eeprom.read(2).unwrap());
+}
+
+// Only zero could be programmed over a programmed half-word, so page with a different magic
+// cannot be retired
+#[test]
+fn test_retire_page_magic() {
+    let mut mcu = MockFlash::erased(1024, 4);
+    let mut eeprom = EEPROM::new(layout(0, 4), &mut mcu);
+    eeprom.init().unwrap();
+    eeprom.flash.flash_mem[512 + 2] = 0x1234;
+    eeprom.flash.flash_mem[512 + 10] = 0x1234;
+    eeprom.flash.flash_mem[1024 + 10] = 0x1234;
+
+    assert_eq!(Ok(false), eeprom.retire_page(1));
+    assert_eq!(0x1234, eeprom.flash.flash_mem[512 + 2]);
+    assert!(!eeprom.is_bad_page(1));
+    assert_eq!(Ok(true), eeprom.retire_page(2));
+    assert!(eeprom.is_bad_page(2));
+}
+
+// check() tests
+
+#[test]
+fn test_check() {
+    let mut mcu = MockFlash::load("src/tests/test-data/valid-simple.txt", 1024, 2);
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    let report = Report {
+        active: Some(0),
+        error: None,
+        active_pages: 1,
+        retired_pages: 0,
+        unknown_pages: 0,
+        estimated_erases: Some(0),
+        live_items: 2,
+        stale_items: 0,
+        erased_items: 504,
+        corrupt_items: 0,
+        trailing_items: 0,
+    };
+    assert_eq!(report, eeprom.check());
+    assert!(report.is_consistent());
+    assert_eq!(PageState::Erased, eeprom.check_page(1).state);
+}
+
+#[test]
+fn test_check_two_active() {
+    let mut mcu = MockFlash::load(
+        "src/tests/test-data/two-active-rescue-interrupted.txt",
+        1024,
+        2,
+    );
+    let report = mcu.eeprom(layout(0, 2)).check();
+    assert_eq!(Some(1), report.active);
+    assert_eq!(Some(1), report.estimated_erases);
+    assert_eq!(2, report.active_pages);
+    assert_eq!(4, report.live_items);
+    assert_eq!(251, report.corrupt_items);
+    assert!(!report.is_consistent());
+    assert_flash(
+        &mcu,
+        "src/tests/test-data/two-active-rescue-interrupted.txt",
+    );
+}
+
+#[test]
+fn test_check_unknown() {
+    let mut mcu = MockFlash::load("src/tests/test-data/unknown-format.txt", 1024, 2);
+    let report = mcu.eeprom(layout(0, 2)).check();
+    assert_eq!(None, report.active);
+    assert_eq!(Some(Error::UnknownFormat), report.error);
+    assert!(!report.is_consistent());
+    assert_flash(&mcu, "src/tests/test-data/unknown-format.txt");
+
+    let mut mcu = MockFlash::load("src/tests/test-data/zeroed.txt", 1024, 2);
+    let report = mcu.eeprom(layout(0, 2)).check();
+    assert_eq!(None, report.active);
+    assert_eq!(2, report.unknown_pages);
+    assert!(!report.is_consistent());
+}
+
+#[test]
+fn test_check_page() {
+    let mut mcu = MockFlash::load("src/tests/test-data/transaction-interrupted.txt", 1024, 2);
+    let report = PageReport {
+        state: PageState::Active,
+        generation: Some(0),
+        live_items: 1,
+        stale_items: 3,
+        erased_items: 249,
+        corrupt_items: 0,
+        trailing_items: 0,
+    };
+    assert_eq!(report, mcu.eeprom(layout(0, 2)).check_page(0));
+
+    let mut mcu = MockFlash::load("src/tests/test-data/counter-simple.txt", 1024, 2);
+    let report = mcu.eeprom(layout(0, 2)).check_page(0);
+    assert_eq!(
+        (2, 2, 249),
+        (report.live_items, report.stale_items, report.erased_items)
+    );
+
+    let mut mcu = MockFlash::load("src/tests/test-data/verified-invalid.txt", 1024, 2);
+    let report = mcu.eeprom(layout(0, 2)).check_page(0);
+    assert_eq!((3, 1), (report.live_items, report.corrupt_items));
+
+    let mut mcu = MockFlash::load("src/tests/test-data/valid-simple.txt", 1024, 2);
+    // Item written after the erased one
+    mcu.flash_mem[20] = 0x0005;
+    mcu.flash_mem[21] = 0x1234;
+    let report = mcu.eeprom(layout(0, 2)).check_page(0);
+    assert_eq!(
+        (3, 250, 1),
+        (
+            report.live_items,
+            report.erased_items,
+            report.trailing_items
+        )
+    );
+}
+
+#[test]
+fn test_check_compacted() {
+    let mut mcu = MockFlash::erased(1024, 3);
+    let mut eeprom = EEPROM::new(layout(0, 3), &mut mcu);
+    eeprom.init().unwrap();
+    eeprom.write(1, 0xdead).unwrap();
+    eeprom.compact().unwrap();
+    eeprom.flash.flash_mem[1024] = 0; // dirty the next page
+    eeprom.flash.faulty_erases = 1 + ERASE_RETRIES;
+    eeprom.set_verify(true);
+    eeprom.compact().unwrap();
+
+    assert_eq!(PageState::Obsolete, eeprom.check_page(1).state);
+    assert_eq!(Some(1), eeprom.check_page(1).generation);
+    assert_eq!(1, eeprom.check_page(1).stale_items);
+    assert_eq!(PageState::Retired, eeprom.check_page(2).state);
+    let report = eeprom.check();
+    assert_eq!(Some(0), report.active);
+    assert_eq!(Some(1), report.estimated_erases);
+    assert_eq!(1, report.retired_pages);
+    assert!(report.is_consistent());
+}
+
+// compact() and prepare() tests
+
+#[test]
+fn test_compact() {
+    test(
+        "src/tests/test-data/valid-simple.txt",
+        "src/tests/test-data/compacted-simple.txt",
+        |eeprom| eeprom.compact().unwrap(),
+    );
+}
+
+#[test]
+fn test_compact_step() {
+    test(
+        "src/tests/test-data/valid-simple.txt",
+        "src/tests/test-data/compacted-simple-steps.txt",
+        |eeprom| {
+            assert!(!eeprom.compact_step(1).unwrap());
+            assert!(!eeprom.compact_step(1).unwrap());
+            assert!(eeprom.compact_step(1).unwrap());
+        },
+    );
+}
+
+#[test]
+fn test_compact_step_interleaved() {
+    let mut mcu = MockFlash::load("src/tests/test-data/valid-simple.txt", 1024, 2);
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+
+    assert!(!eeprom.compact_step(1).unwrap());
+    eeprom.write(1, 0x1111).unwrap();
+    assert_eq!(0x1111, eeprom.read(1).unwrap());
+    assert!(!eeprom.compact_step(2).unwrap());
+    eeprom.write(3, 0x3333).unwrap();
+    assert!(eeprom.compact_step(10).unwrap());
+
+    assert_eq!(0x1111, eeprom.read(1).unwrap());
+    assert_eq!(0xbeef, eeprom.read(2).unwrap());
+    assert_eq!(0x3333, eeprom.read(3).unwrap());
+    // Source page is now obsolete
+    assert_eq!(0x0000, mcu.flash_mem[0]);
+}
+
+#[test]
+fn test_compact_step_interrupted() {
+    let mut mcu = MockFlash::load("src/tests/test-data/valid-simple.txt", 1024, 2);
+    assert!(!mcu.eeprom(layout(0, 2)).compact_step(1).unwrap());
+
+    // Power loss, target page is not active yet
+    mcu.eeprom(layout(0, 2)).init().unwrap();
+    assert_flash(&mcu, "src/tests/test-data/valid-simple.txt");
+}
+
+#[test]
+fn test_prepare_rescued() {
+    test(
+        "src/tests/test-data/rescued-full-bogus.txt",
+        "src/tests/test-data/valid-simple-third.txt",
+        |eeprom| eeprom.prepare().unwrap(),
+    );
+}
+
+#[test]
+fn test_prepare_prepared() {
+    test(
+        "src/tests/test-data/valid-simple.txt",
+        "src/tests/test-data/valid-simple.txt",
+        |eeprom| eeprom.prepare().unwrap(),
+    );
+}
+
+#[test]
+fn test_init_rescued() {
+    test_init(
+        "src/tests/test-data/rescued-full-bogus.txt",
+        "src/tests/test-data/valid-simple-third.txt",
+    )
+}
+
+// migrate_layout() tests
+
+fn layout(first_page: u32, page_count: u32) -> Params {
+    Params {
+        first_page,
+        flash_size: 64 * 1024,
+        page_size: 1024,
+        page_count,
+    }
+}
+
+fn test_migrate(initial: &str, expected: &str, old: Params, new: Params) {
+    let mut mcu = MockFlash::load(initial, 1024, 4);
+    let mut eeprom = mcu.eeprom(old);
+
+    eeprom.migrate_layout(old, new).unwrap();
+    assert_eq!(0xdead, eeprom.read(1).unwrap());
+    assert_eq!(0xbeef, eeprom.read(2).unwrap());
+    // Migration is complete, so running it again changes nothing
+    eeprom.migrate_layout(old, new).unwrap();
+
+    assert_flash(&mcu, expected);
+}
+
+#[test]
+fn test_migrate_grow() {
+    test_migrate(
+        "src/tests/test-data/layout/two-pages.txt",
+        "src/tests/test-data/layout/grown.txt",
+        layout(0, 2),
+        layout(0, 4),
+    )
+}
+
+#[test]
+fn test_migrate_grow_interrupted() {
+    test_migrate(
+        "src/tests/test-data/layout/grow-interrupted.txt",
+        "src/tests/test-data/layout/grown.txt",
+        layout(0, 2),
+        layout(0, 4),
+    )
+}
+
+#[test]
+fn test_migrate_move() {
+    test_migrate(
+        "src/tests/test-data/layout/two-pages.txt",
+        "src/tests/test-data/layout/moved.txt",
+        layout(0, 2),
+        layout(2, 2),
+    )
+}
+
+#[test]
+fn test_migrate_shrink() {
+    test_migrate(
+        "src/tests/test-data/layout/four-pages.txt",
+        "src/tests/test-data/layout/shrunk.txt",
+        layout(0, 4),
+        layout(0, 2),
+    )
+}
+
+// Retired page of the old region is kept retired
+#[test]
+fn test_migrate_retired() {
+    let mut mcu = MockFlash::erased(1024, 4);
+    let mut eeprom = EEPROM::new(layout(0, 4), &mut mcu);
+    eeprom.init().unwrap();
+    eeprom.write(1, 0xdead).unwrap();
+    assert_eq!(Ok(true), eeprom.retire_page(3));
+
+    eeprom.migrate_layout(layout(0, 4), layout(1, 3)).unwrap();
+    assert_eq!(0xdead, eeprom.read(1).unwrap());
+    assert!(eeprom.is_bad_page(2));
+    for _ in 0..4 {
+        eeprom.compact().unwrap();
+        assert_ne!(Some(2), eeprom.find_active());
+    }
+    assert_eq!(0xdead, eeprom.read(1).unwrap());
+}
+
+#[test]
+fn test_migrate_invalid() {
+    let mut mcu = MockFlash::load("src/tests/test-data/layout/two-pages.txt", 1024, 4);
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    assert_eq!(
+        Err(Error::InvalidParams(ParamsError::TooFewPages)),
+        eeprom.migrate_layout(layout(0, 2), layout(2, 1))
+    );
+    let new = Params {
+        page_size: 2048,
+        ..layout(1, 2)
+    };
+    assert_eq!(
+        Err(Error::InvalidParams(ParamsError::PageSizeMismatch)),
+        eeprom.migrate_layout(layout(0, 2), new)
+    );
+    assert_flash(&mcu, "src/tests/test-data/layout/two-pages.txt");
+}
+
+// New region has no usable page besides the active page of the old region
+#[test]
+fn test_migrate_no_target() {
+    let mut mcu = MockFlash::erased(1024, 4);
+    let mut eeprom = EEPROM::new(layout(0, 4), &mut mcu);
+    eeprom.init().unwrap();
+    eeprom.write(1, 0xdead).unwrap();
+    assert_eq!(Some(0), eeprom.find_active());
+    assert_eq!(Ok(true), eeprom.retire_page(1));
+
+    assert_eq!(
+        Err(Error::InvalidParams(ParamsError::TooFewPages)),
+        eeprom.migrate_layout(layout(0, 4), layout(0, 2))
+    );
+    assert_eq!(0xdead, eeprom.read(1).unwrap());
+}
+
+// Params validation tests
+
+#[test]
+fn test_params_validate() {
+    assert_eq!(Ok(()), layout(0, 2).validate());
+    assert_eq!(Ok(()), layout(62, 2).validate());
+    assert_eq!(Err(ParamsError::OutOfBounds), layout(63, 2).validate());
+    assert_eq!(
+        Err(ParamsError::OutOfBounds),
+        layout(u32::MAX, 2).validate()
+    );
+    assert_eq!(Err(ParamsError::TooFewPages), layout(0, 1).validate());
+
+    let params = Params {
+        page_size: 0,
+        ..layout(0, 2)
+    };
+    assert_eq!(Err(ParamsError::InvalidPageSize), params.validate());
+    let params = Params {
+        page_size: 130,
+        ..layout(0, 2)
+    };
+    assert_eq!(Err(ParamsError::InvalidPageSize), params.validate());
+    let params = Params {
+        page_size: 12,
+        ..layout(0, 2)
+    };
+    assert_eq!(Err(ParamsError::PageTooSmall), params.validate());
+    // Page header and the reserved items leave no room for values
+    let params = Params {
+        page_size: 16,
+        ..layout(0, 2)
+    };
+    assert_eq!(Err(ParamsError::PageTooSmall), params.validate());
+}
+
+#[test]
+fn test_params_validate_program_end() {
+    assert_eq!(Ok(()), layout(60, 2).validate_program_end(60 * 1024));
+    assert_eq!(Ok(()), layout(60, 2).validate_program_end(0));
+    assert_eq!(
+        Err(ParamsError::OverlapsProgram),
+        layout(60, 2).validate_program_end(60 * 1024 + 4)
+    );
+    // Geometry is validated first
+    assert_eq!(
+        Err(ParamsError::OutOfBounds),
+        layout(63, 2).validate_program_end(0)
+    );
+}
+
+#[test]
+fn test_try_new() {
+    let mut mcu = MockFlash::load("src/tests/test-data/valid-simple.txt", 1024, 2);
+    assert!(EEPROM::try_new(layout(0, 1), &mut mcu).is_err());
+
+    let mut eeprom = EEPROM::try_new(layout(0, 2), &mut mcu).unwrap();
+    eeprom.init().unwrap();
+    assert_eq!(0xdead, eeprom.read(1).unwrap());
+}
+
+#[test]
+fn test_params_from_region() {
+    let params = Params::from_region(0x0800_f000, 1024, 4, 64 * 1024).unwrap();
+    assert_eq!(60, params.first_page);
+    assert_eq!(1024, params.page_size);
+    assert_eq!(4, params.page_count);
+
+    assert_eq!(
+        Err(ParamsError::UnalignedStart),
+        Params::from_region(0x0800_f200, 1024, 4, 64 * 1024).map(|_| ())
+    );
+    assert_eq!(
+        Err(ParamsError::InvalidPageSize),
+        Params::from_region(0x0800_f000, 0, 4, 64 * 1024).map(|_| ())
+    );
+    assert_eq!(
+        Err(ParamsError::OutOfBounds),
+        Params::from_region(0x0700_f000, 1024, 4, 64 * 1024).map(|_| ())
+    );
+    assert_eq!(
+        Err(ParamsError::OutOfBounds),
+        Params::from_region(0x0800_f000, 1024, 8, 64 * 1024).map(|_| ())
+    );
+}
+
+// STM32L0 has 128-byte pages
+#[test]
+fn test_small_pages() {
+    let mut mcu = MockFlash::erased(128, 2);
+    let params = Params {
+        first_page: 0,
+        flash_size: 64 * 1024,
+        page_size: 128,
+        page_count: 2,
+    };
+    let mut eeprom = EEPROM::try_new(params, &mut mcu).unwrap();
+    eeprom.init().unwrap();
+
+    for value in 0..100 {
+        eeprom.write(1, value).unwrap();
+        eeprom.write(2, !value).unwrap();
+    }
+    assert_eq!(99, eeprom.read(1).unwrap());
+    assert_eq!(!99, eeprom.read(2).unwrap());
+}
+
+#[test]
+fn test_const_layout() {
+    type Layout = ConstLayout<0, 1024, 2, { 64 * 1024 }>;
+    assert_eq!(256, Layout::PAGE_ITEMS);
+    assert_eq!(251, Layout::CAPACITY);
+
+    let mut mcu = MockFlash::load("src/tests/test-data/valid-simple.txt", 1024, 2);
+    let mut eeprom = EEPROM::with_layout(Layout::default(), &mut mcu);
+    eeprom.init().unwrap();
+    assert_eq!(Layout::CAPACITY as u32, eeprom.capacity());
+    assert_eq!(0xdead, eeprom.read(1).unwrap());
+    eeprom.write(3, 0xacdb).unwrap();
+    assert_eq!(0xacdb, eeprom.read(3).unwrap());
+}
+
+#[test]
+fn test_capacity() {
+    let mut mcu = MockFlash::erased(128, 2);
+    let params = Params {
+        first_page: 0,
+        flash_size: 64 * 1024,
+        page_size: 128,
+        page_count: 2,
+    };
+    let mut eeprom = EEPROM::try_new(params, &mut mcu).unwrap();
+    eeprom.init().unwrap();
+    assert_eq!(27, eeprom.capacity());
+    assert_eq!(27, eeprom.free_slots());
+
+    for tag in 0..27 {
+        eeprom.write(tag, tag).unwrap();
+    }
+    assert_eq!(27, eeprom.live_tags());
+    assert_eq!(0, eeprom.free_slots());
+    assert_eq!(Err(Error::CapacityExceeded), eeprom.write(27, 0));
+    assert_eq!(Err(Error::CapacityExceeded), eeprom.try_write(27, 0));
+
+    // Existing tags could still be overwritten, compacting the page as needed
+    for value in 0..108 {
+        eeprom.write(value % 27, value).unwrap();
+    }
+    for tag in 0..27 {
+        assert_eq!(tag + 81, eeprom.read(tag).unwrap());
+    }
+    assert!(eeprom.read(27).is_none());
+}
+
+#[test]
+fn test_live_tags_counted() {
+    let mut mcu = MockFlash::load("src/tests/test-data/full-simple.txt", 1024, 2);
+    let mut eeprom = mcu.eeprom(layout(0, 2));
+    assert_eq!(251, eeprom.capacity());
+    assert_eq!(2, eeprom.live_tags());
+    assert_eq!(249, eeprom.free_slots());
+}
@@ -0,0 +1,116 @@
+use crate::mtd::{MtdInfoUser, MEMERASE, MEMGETINFO};
+use crate::{EEPROMExt, Flash, MtdFlash, MtdInfo, Params};
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+fn params() -> Params {
+    Params {
+        first_page: 1,
+        flash_size: 64 * 1024,
+        page_size: 1024,
+        page_count: 2,
+    }
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("eeprom-mtd-{}-{}.bin", name, process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn test_mtd_open_requires_device() {
+    let path = temp_path("device");
+    fs::write(&path, [0xffu8; 16]).unwrap();
+    let result = MtdFlash::open(&path);
+    fs::remove_file(&path).unwrap();
+    assert!(result.is_err());
+}
+
+#[test]
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "arm"))]
+fn test_mtd_ioctl_numbers() {
+    assert_eq!(0x8020_4d01, MEMGETINFO);
+    assert_eq!(0x4008_4d02, MEMERASE);
+}
+
+#[test]
+fn test_mtd_geometry() {
+    let nor = MtdInfoUser {
+        kind: 3,
+        size: 4096,
+        erasesize: 1024,
+        writesize: 1,
+        ..MtdInfoUser::default()
+    };
+    let info = MtdInfo {
+        size: 4096,
+        erase_size: 1024,
+        write_size: 1,
+    };
+    assert_eq!(info, nor.geometry().unwrap());
+
+    // NAND flash
+    let nand = MtdInfoUser { kind: 4, ..nor };
+    assert!(nand.geometry().is_err());
+    // Write unit is larger than a half-word
+    let wide = MtdInfoUser {
+        writesize: 32,
+        ..nor
+    };
+    assert!(wide.geometry().is_err());
+}
+
+#[test]
+fn test_mtd_emulated_erase() {
+    let path = temp_path("erase");
+    let params = params();
+    let mut mtd = MtdFlash::open_emulated(&path, 4096, 1024).unwrap();
+    assert_eq!(
+        &MtdInfo {
+            size: 4096,
+            erase_size: 1024,
+            write_size: 1,
+        },
+        mtd.info()
+    );
+
+    let mut flash = &mut mtd;
+    flash.write(&params, 1024, 0x1234).unwrap();
+    flash.write(&params, 1026, 0xff0f).unwrap();
+    flash.write(&params, 1026, 0xf0ff).unwrap();
+    assert_eq!(0x1234, flash.read(&params, 1024).unwrap());
+    assert_eq!(0xf00f, flash.read(&params, 1026).unwrap());
+
+    // Misaligned and out of range erases are rejected
+    assert!(flash.page_erase(&params, 1026).is_err());
+    assert!(flash.page_erase(&params, 4096).is_err());
+
+    flash.page_erase(&params, 1024).unwrap();
+    assert_eq!(0xffff, flash.read(&params, 1024).unwrap());
+    assert_eq!(0xffff, flash.read(&params, 1026).unwrap());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_mtd_emulated_eeprom() {
+    let path = temp_path("eeprom");
+    let params = params();
+    {
+        let mut mtd = MtdFlash::open_emulated(&path, 4096, 1024).unwrap();
+        let mut eeprom = mtd.eeprom(params);
+        eeprom.init().unwrap();
+        for value in 0..1000 {
+            eeprom.write(1, value).unwrap();
+        }
+        eeprom.write(2, 0xbeef).unwrap();
+    }
+
+    let mut mtd = MtdFlash::open_emulated(&path, 4096, 1024).unwrap();
+    let mut eeprom = mtd.eeprom(params);
+    eeprom.init().unwrap();
+    assert_eq!(999, eeprom.read(1).unwrap());
+    assert_eq!(0xbeef, eeprom.read(2).unwrap());
+    fs::remove_file(&path).unwrap();
+}
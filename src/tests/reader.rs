@@ -0,0 +1,95 @@
+use super::{assert_flash, layout, MockFlash};
+use crate::{EEPROMReader, Error, FlashResult, HalfWord, MappedFlash, Params, ReadFlash};
+
+// Flash which fails every read
+struct FailingFlash;
+
+impl ReadFlash for FailingFlash {
+    fn read(&mut self, _params: &Params, _offset: u32) -> FlashResult<HalfWord> {
+        Err(())
+    }
+}
+
+fn reader(mcu: &mut MockFlash) -> EEPROMReader<&mut MockFlash> {
+    EEPROMReader::new(layout(0, 2), mcu)
+}
+
+#[test]
+fn test_reader() {
+    let mut mcu = MockFlash::load("src/tests/test-data/valid-simple.txt", 1024, 2);
+    let mut eeprom = reader(&mut mcu);
+    assert_eq!(Some(0xdead), eeprom.read(1).unwrap());
+    assert_eq!(Some(0xbeef), eeprom.read(2).unwrap());
+    assert_eq!(None, eeprom.read(3).unwrap());
+    assert_flash(&mcu, "src/tests/test-data/valid-simple.txt");
+}
+
+#[test]
+fn test_reader_two_active() {
+    let mut mcu = MockFlash::load(
+        "src/tests/test-data/two-active-rescue-interrupted.txt",
+        1024,
+        2,
+    );
+    let mut eeprom = reader(&mut mcu);
+    assert_eq!(Some(0xdead), eeprom.read(1).unwrap());
+    assert_eq!(Some(0xbeef), eeprom.read(2).unwrap());
+    assert_flash(
+        &mcu,
+        "src/tests/test-data/two-active-rescue-interrupted.txt",
+    );
+}
+
+#[test]
+fn test_reader_transaction_interrupted() {
+    let mut mcu = MockFlash::load("src/tests/test-data/transaction-interrupted.txt", 1024, 2);
+    assert_eq!(Some(0xdead), reader(&mut mcu).read(1).unwrap());
+    assert_flash(&mcu, "src/tests/test-data/transaction-interrupted.txt");
+}
+
+#[test]
+fn test_reader_legacy() {
+    let mut mcu = MockFlash::load("src/tests/test-data/legacy/valid-simple.txt", 1024, 2);
+    let mut eeprom = reader(&mut mcu);
+    assert_eq!(Some(0xdead), eeprom.read(1).unwrap());
+    assert_eq!(Some(0xbeef), eeprom.read(2).unwrap());
+
+    // Target page of the migration is not activated yet
+    let mut mcu = MockFlash::load(
+        "src/tests/test-data/legacy/migration-not-activated.txt",
+        1024,
+        2,
+    );
+    assert_eq!(Some(0xdead), reader(&mut mcu).read(1).unwrap());
+}
+
+#[test]
+fn test_reader_invalid() {
+    for filename in [
+        "src/tests/test-data/erased.txt",
+        "src/tests/test-data/zeroed.txt",
+        "src/tests/test-data/unknown-format.txt",
+        "src/tests/test-data/unsupported-version.txt",
+        "src/tests/test-data/params-mismatch.txt",
+    ] {
+        let mut mcu = MockFlash::load(filename, 1024, 2);
+        assert_eq!(None, reader(&mut mcu).read(1).unwrap());
+        assert_flash(&mcu, filename);
+    }
+}
+
+#[test]
+fn test_reader_mapped() {
+    let mcu = MockFlash::load("src/tests/test-data/valid-simple.txt", 1024, 2);
+    let flash = unsafe { MappedFlash::new(mcu.flash_mem.as_ptr().cast()) };
+    let mut eeprom = EEPROMReader::new(layout(0, 2), flash);
+    assert_eq!(Some(0xdead), eeprom.read(1).unwrap());
+    assert_eq!(Some(0xbeef), eeprom.read(2).unwrap());
+    assert_eq!(None, eeprom.read(3).unwrap());
+}
+
+#[test]
+fn test_reader_flash_error() {
+    let mut eeprom = EEPROMReader::new(layout(0, 2), FailingFlash);
+    assert_eq!(Err(Error::Flash(())), eeprom.read(1));
+}
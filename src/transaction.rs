@@ -0,0 +1,47 @@
+use crate::HalfWord;
+
+/// Maximum amount of distinct tags written by a single transaction
+pub const MAX_TRANSACTION_ITEMS: usize = 16;
+
+/// Values collected by the `EEPROM::transaction` closure. Values are buffered in RAM and
+/// written to the flash after the closure returns, so either all of them or none of them are
+/// stored.
+pub struct Transaction {
+    // Values to write, first `len` entries are used
+    entries: [(HalfWord, HalfWord); MAX_TRANSACTION_ITEMS],
+    len: usize,
+}
+
+impl Transaction {
+    pub(crate) fn new() -> Self {
+        Transaction {
+            entries: [(0, 0); MAX_TRANSACTION_ITEMS],
+            len: 0,
+        }
+    }
+
+    /// Write value for a specified tag as a part of the transaction. Repeated writes to the same
+    /// tag replace the buffered value.
+    ///
+    /// # Panics
+    /// * panics if more than `MAX_TRANSACTION_ITEMS` distinct tags are written
+    /// * panics if tag value has the most significant bit set to `1` (reserved value)
+    pub fn write(&mut self, tag: HalfWord, data: HalfWord) {
+        assert_eq!(tag & 0b1000_0000_0000_0000, 0, "msb bit of `1` is reserved");
+
+        if let Some(entry) = self.entries[..self.len].iter_mut().find(|(t, _)| *t == tag) {
+            entry.1 = data;
+            return;
+        }
+        assert!(
+            self.len < MAX_TRANSACTION_ITEMS,
+            "too many values in transaction"
+        );
+        self.entries[self.len] = (tag, data);
+        self.len += 1;
+    }
+
+    pub(crate) fn entries(&self) -> &[(HalfWord, HalfWord)] {
+        &self.entries[..self.len]
+    }
+}